@@ -0,0 +1,454 @@
+use std::collections::HashMap;
+use regex::Regex;
+use crate::GodotMaterialProperty;
+use crate::presets::{NormalConvention, Preset};
+
+/// The built-in synonyms for each channel, as they are found in the filenames
+/// of the common texture sources. Separators and case don't matter, so
+/// "basecolor" also covers "BaseColor" and "Base_Color".
+const BUILTIN_SYNONYMS: [(&str, &[&str]); 11] = [
+    ("albedo", &["albedo", "alb", "basecolor", "color", "colour", "col", "diffuse", "diff"]),
+    ("normal", &["normal", "normalgl", "normaldx", "normalmap", "nrm", "nor", "norgl", "nordx", "norm"]),
+    ("height", &["height", "heightmap", "displacement", "disp", "displace", "bump"]),
+    ("roughness", &["roughness", "rough", "rgh"]),
+    ("metallic", &["metallic", "metalness", "metal", "met"]),
+    ("ao", &["ao", "ambientocclusion", "occlusion", "occ"]),
+    ("emission", &["emission", "emissive", "emit", "glow"]),
+    ("orm", &["orm", "arm", "occlusionroughnessmetallic"]),
+    // Opacity maps are merged into the alpha of the albedo map (see ``--merge-opacity``)
+    ("opacity", &["opacity", "opac", "mask", "alpha", "transparency", "cutout"]),
+    // The specular/glossiness workflow, which has to be converted before Godot can use it
+    ("gloss", &["gloss", "glossiness", "gls", "smoothness"]),
+    ("specular", &["specular", "spec", "spc", "refl", "reflection"]),
+];
+
+/// Resolution tags, such as "4K", "2048" or "1024px", which are found in filenames of any source
+/// Numbers with a leading zero, like Blender's "Rock.001", tell materials apart instead.
+const RESOLUTION_TAG: &str = r"^(?i:\d+k|[1-9]\d{2,4}(px)?)$";
+
+/// The maximum number of tokens a synonym can span, e.g. "ambient occlusion" spans two
+const MAX_SYNONYM_TOKENS: usize = 4;
+
+/// Hint match
+/// The channel detected in a filename, and where in the filename the hint was found
+pub struct HintMatch {
+    pub channel: &'static str,
+    pub start: usize,
+    pub end: usize,
+}
+
+/// Hint dictionary
+/// Maps the words found in filenames (such as "BaseColor" or "NormalGL") to material
+/// channels. Matching is case-insensitive and token-aware: the filename is split
+/// into words at separators and camel case boundaries, so "Rock_Color" matches
+/// "color", while "Colorful_Rock" doesn't.
+///
+/// Synonyms added by the user take precedence over the vendor-specific synonyms of
+/// a preset, which take precedence over the built-in ones.
+#[derive(Debug, Clone)]
+pub struct HintDictionary {
+    builtin: HashMap<String, &'static str>,
+    vendor: HashMap<String, &'static str>,
+    custom: HashMap<String, &'static str>,
+    preset: Option<Preset>,
+    normal_convention: Option<NormalConvention>,
+    resolution_tag: Regex,
+    vendor_tag: Option<Regex>,
+    vendor_suffix: Option<Regex>,
+}
+
+impl Default for HintDictionary {
+    fn default() -> Self {
+        let mut builtin: HashMap<String, &'static str> = HashMap::new();
+
+        for (channel, synonyms) in BUILTIN_SYNONYMS {
+            for synonym in synonyms {
+                builtin.insert(synonym.to_string(), channel);
+            }
+        }
+
+        HintDictionary {
+            builtin,
+            vendor: HashMap::new(),
+            custom: HashMap::new(),
+            preset: None,
+            normal_convention: None,
+            resolution_tag: Regex::new(RESOLUTION_TAG).unwrap(),
+            vendor_tag: None,
+            vendor_suffix: None,
+        }
+    }
+}
+
+impl HintDictionary {
+    /// Create a dictionary which knows the naming conventions of a vendor
+    pub fn with_preset(preset: Preset) -> Self {
+        let mut vendor: HashMap<String, &'static str> = HashMap::new();
+
+        for (channel, synonyms) in preset.synonyms() {
+            for synonym in synonyms.iter() {
+                vendor.insert(synonym.to_string(), channel);
+            }
+        }
+
+        HintDictionary {
+            vendor,
+            preset: Some(preset),
+            vendor_tag: preset.tag_expression().map(|expression| Regex::new(expression).unwrap()),
+            vendor_suffix: preset.suffix_expression().map(|expression| Regex::new(expression).unwrap()),
+            ..HintDictionary::default()
+        }
+    }
+
+    /// The vendor preset, if one is used
+    pub fn preset(&self) -> Option<Preset> {
+        self.preset
+    }
+
+    /// Assume normal maps follow ``convention``, instead of the convention of the preset
+    pub fn set_normal_convention(&mut self, convention: NormalConvention) {
+        self.normal_convention = Some(convention);
+    }
+
+    /// The convention normal maps are assumed to follow, unless their filename says otherwise
+    pub fn normal_convention(&self) -> NormalConvention {
+        self.normal_convention
+            .or(self.preset.map(|preset| preset.normal_convention()))
+            .unwrap_or(NormalConvention::OpenGl)
+    }
+
+    /// The convention of a normal map, as stated by its hint (e.g. "NormalDX" or "nor_gl"),
+    /// or otherwise the assumed convention
+    pub fn detect_normal_convention(&self, filename: &str) -> NormalConvention {
+        let hint: String = self.detect(filename)
+            .filter(|hint_match| hint_match.channel == "normal")
+            .map(|hint_match| normalize(&filename[hint_match.start..hint_match.end]))
+            .unwrap_or_default();
+
+        if hint.ends_with("dx") || hint.ends_with("directx") {
+            NormalConvention::DirectX
+        } else if hint.ends_with("gl") || hint.ends_with("opengl") {
+            NormalConvention::OpenGl
+        } else {
+            self.normal_convention()
+        }
+    }
+
+    /// Whether a word from a filename is a tag, such as a resolution ("4K"),
+    /// rather than part of the name of the material
+    pub fn is_tag(&self, word: &str) -> bool {
+        self.resolution_tag.is_match(word)
+            || self.vendor_tag.as_ref().is_some_and(|tag| tag.is_match(word))
+    }
+
+    /// Add a user-supplied synonym, such as "paint" for "albedo"
+    /// Fails if the channel isn't one of the supported channels.
+    pub fn add(&mut self, synonym: &str, channel: &str) -> Result<(), String> {
+        let channel = HintDictionary::channel(channel)?;
+        self.custom.insert(normalize(synonym), channel);
+
+        Ok(())
+    }
+
+    /// The supported channel with the given name, in any case, e.g. "albedo" for "Albedo"
+    /// Fails if the channel isn't one of the supported channels.
+    pub fn channel(name: &str) -> Result<&'static str, String> {
+        BUILTIN_SYNONYMS
+            .iter()
+            .map(|(channel, _)| *channel)
+            .find(|channel| channel.eq_ignore_ascii_case(name))
+            .ok_or_else(|| format!("\"{}\" is not a supported channel", name))
+    }
+
+    /// Detect the channel in a filename
+    ///
+    /// The synonym found last wins, since the channel tag comes after the name of the
+    /// material, which may contain synonyms too: "metal_plate_ao_4k" is an ambient occlusion
+    /// map, not a metallic one. Of the synonyms ending at the same word, the longest wins,
+    /// so "AmbientOcclusion" isn't taken for just "Occlusion".
+    pub fn detect(&self, filename: &str) -> Option<HintMatch> {
+        // Leave out the vendor's suffix, so it isn't mistaken for a channel
+        let filename: &str = match self.vendor_suffix.as_ref().and_then(|suffix| suffix.find(filename)) {
+            Some(suffix) => &filename[..suffix.start()],
+            None => filename,
+        };
+        let tokens = tokenize(filename);
+
+        // User-supplied synonyms are consulted first, then those of the vendor
+        for table in [&self.custom, &self.vendor, &self.builtin] {
+            let mut best: Option<HintMatch> = None;
+
+            for first in 0..tokens.len() {
+                let mut joined = String::new();
+
+                for last in first..tokens.len().min(first + MAX_SYNONYM_TOKENS) {
+                    let (start, end) = tokens[last];
+                    joined.push_str(&filename[start..end].to_lowercase());
+
+                    if let Some(channel) = table.get(&joined) {
+                        // Later matches win, and of those ending at the same word, the longer one
+                        let start = tokens[first].0;
+                        let is_better = match &best {
+                            Some(best) => end > best.end || (end == best.end && start < best.start),
+                            None => true,
+                        };
+
+                        if is_better {
+                            best = Some(HintMatch { channel, start, end });
+                        }
+                    }
+                }
+            }
+
+            if best.is_some() {
+                return best;
+            }
+        }
+
+        None
+    }
+
+    /// Find the ``GodotMaterialProperty`` the filename hints at
+    pub(crate) fn property(&self, filename: &str) -> Option<GodotMaterialProperty> {
+        crate::get_godot_property_from_hint(self.detect(filename)?.channel)
+    }
+}
+
+/// Lowercase the synonym, and drop separators, so it can be compared with joined tokens
+fn normalize(synonym: &str) -> String {
+    synonym
+        .chars()
+        .filter(|c| c.is_alphanumeric())
+        .flat_map(|c| c.to_lowercase())
+        .collect()
+}
+
+/// Split a filename into words, returned as byte ranges
+///
+/// Words are separated by non-alphanumeric characters (such as "_" or "."), by changes
+/// from lower to upper case ("BaseColor"), by the end of an uppercase run ("AOMap"),
+/// and by changes between letters and digits ("Rock042").
+fn tokenize(name: &str) -> Vec<(usize, usize)> {
+    let chars: Vec<(usize, char)> = name.char_indices().collect();
+    let mut tokens: Vec<(usize, usize)> = Vec::new();
+    let mut start: Option<usize> = None;
+
+    for (i, &(offset, c)) in chars.iter().enumerate() {
+        if !c.is_alphanumeric() {
+            if let Some(token_start) = start.take() {
+                tokens.push((token_start, offset));
+            }
+            continue;
+        }
+
+        if let (Some(token_start), Some(&(_, prev))) = (start, i.checked_sub(1).and_then(|p| chars.get(p))) {
+            let next = chars.get(i + 1).map(|&(_, next)| next);
+            let is_boundary = (prev.is_lowercase() && c.is_uppercase())
+                || (prev.is_uppercase() && c.is_uppercase() && next.is_some_and(|n| n.is_lowercase()))
+                || (prev.is_ascii_digit() != c.is_ascii_digit());
+
+            if is_boundary {
+                tokens.push((token_start, offset));
+                start = Some(offset);
+            }
+        } else if start.is_none() {
+            start = Some(offset);
+        }
+    }
+
+    if let Some(token_start) = start {
+        tokens.push((token_start, name.len()));
+    }
+
+    tokens
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The words of the name, as split by ``tokenize``
+    fn words(name: &str) -> Vec<&str> {
+        tokenize(name).into_iter().map(|(start, end)| &name[start..end]).collect()
+    }
+
+    /// Check the channel detected in each of the filenames
+    fn assert_channels(dictionary: &HintDictionary, cases: &[(&str, Option<&str>)]) {
+        for (filename, expected) in cases {
+            let channel = dictionary.detect(filename).map(|hint_match| hint_match.channel);
+            assert_eq!(channel, *expected, "channel of {}", filename);
+        }
+    }
+
+    #[test]
+    fn tokenize_splits_separators_case_and_digits() {
+        assert_eq!(words("rock_wall_diff_4k"), ["rock", "wall", "diff", "4", "k"]);
+        assert_eq!(words("Rock042_4K-PNG_NormalGL"), ["Rock", "042", "4", "K", "PNG", "Normal", "GL"]);
+        assert_eq!(words("Metal_Plate_BaseColor"), ["Metal", "Plate", "Base", "Color"]);
+        assert_eq!(words("AOMap"), ["AO", "Map"]);
+        assert_eq!(words("T_Brick_ORM"), ["T", "Brick", "ORM"]);
+        assert_eq!(words("__a..b  "), ["a", "b"]);
+        assert!(words("").is_empty());
+    }
+
+    #[test]
+    fn detect_ambientcg() {
+        assert_channels(&HintDictionary::default(), &[
+            ("Rock042_4K-PNG_Color", Some("albedo")),
+            ("Rock042_4K-PNG_NormalGL", Some("normal")),
+            ("Rock042_4K-PNG_NormalDX", Some("normal")),
+            ("Rock042_4K-PNG_Displacement", Some("height")),
+            ("Rock042_4K-PNG_Roughness", Some("roughness")),
+            ("Rock042_4K-PNG_AmbientOcclusion", Some("ao")),
+            ("Metal032_4K_AO", Some("ao")),
+            ("Metal032_4K_Color", Some("albedo")),
+            ("Metal032_4K_Metalness", Some("metallic")),
+            ("Metal032_4K_Roughness", Some("roughness")),
+            ("Metal032_4K_Opacity", Some("opacity")),
+        ]);
+    }
+
+    #[test]
+    fn detect_polyhaven() {
+        assert_channels(&HintDictionary::default(), &[
+            ("metal_plate_diff_4k", Some("albedo")),
+            ("metal_plate_ao_4k", Some("ao")),
+            ("metal_plate_nor_gl_4k", Some("normal")),
+            ("metal_plate_nor_dx_4k", Some("normal")),
+            ("metal_plate_rough_4k", Some("roughness")),
+            ("metal_plate_metal_4k", Some("metallic")),
+            ("metal_plate_disp_4k", Some("height")),
+            ("metal_plate_arm_4k", Some("orm")),
+            ("rock_wall_diff_4k", Some("albedo")),
+        ]);
+    }
+
+    #[test]
+    fn detect_poliigon() {
+        assert_channels(&HintDictionary::default(), &[
+            ("WoodFlooring044_COL_VAR1_3K", Some("albedo")),
+            ("WoodFlooring044_NRM_3K", Some("normal")),
+            ("WoodFlooring044_DISP_3K", Some("height")),
+            ("WoodFlooring044_GLOSS_3K", Some("gloss")),
+            ("WoodFlooring044_REFL_3K", Some("specular")),
+            ("WoodFlooring044_AO_3K", Some("ao")),
+        ]);
+
+        // The workflow suffix is only ignored with the preset
+        assert_channels(&HintDictionary::with_preset(Preset::Poliigon), &[
+            ("WoodFlooring044_COL_VAR1_3K_METALNESS", Some("albedo")),
+            ("WoodFlooring044_ROUGHNESS_3K_METALNESS", Some("roughness")),
+            ("WoodFlooring044_METALNESS_3K_METALNESS", Some("metallic")),
+            ("WoodFlooring044_COL_VAR2_3K_SPECULAR", Some("albedo")),
+        ]);
+    }
+
+    #[test]
+    fn detect_megascans() {
+        assert_channels(&HintDictionary::default(), &[
+            ("vbhmeibdw_4K_Albedo", Some("albedo")),
+            ("vbhmeibdw_4K_Normal", Some("normal")),
+            ("vbhmeibdw_4K_Displacement", Some("height")),
+            ("vbhmeibdw_4K_Roughness", Some("roughness")),
+            ("vbhmeibdw_4K_AO", Some("ao")),
+            ("vbhmeibdw_4K_Opacity", Some("opacity")),
+            ("vbhmeibdw_4K_Specular", Some("specular")),
+            ("vbhmeibdw_4K_Gloss", Some("gloss")),
+        ]);
+    }
+
+    #[test]
+    fn detect_substance() {
+        assert_channels(&HintDictionary::default(), &[
+            ("Metal_Plate_BaseColor", Some("albedo")),
+            ("Metal_Plate_Base_Color", Some("albedo")),
+            ("Metal_Plate_Normal", Some("normal")),
+            ("Metal_Plate_Height", Some("height")),
+            ("Metal_Plate_Roughness", Some("roughness")),
+            ("Metal_Plate_Metallic", Some("metallic")),
+            ("Metal_Plate_Emissive", Some("emission")),
+            ("Metal_Plate_OcclusionRoughnessMetallic", Some("orm")),
+            ("Metal_Plate_ORM", Some("orm")),
+        ]);
+
+        assert_channels(&HintDictionary::with_preset(Preset::Substance), &[
+            ("Metal_Plate_Mixed_AO", Some("ao")),
+            ("Metal_Plate_Normal_OpenGL", Some("normal")),
+        ]);
+    }
+
+    #[test]
+    fn detect_only_whole_words() {
+        assert_channels(&HintDictionary::default(), &[
+            ("Colorful_Rock", None),
+            ("Normality", None),
+            ("Farm_House", None),
+            ("rock", None),
+            ("", None),
+        ]);
+    }
+
+    #[test]
+    fn detect_reports_the_span_of_the_hint() {
+        let dictionary = HintDictionary::default();
+
+        let hint_match = dictionary.detect("Rock042_4K-PNG_AmbientOcclusion").unwrap();
+        assert_eq!((hint_match.start, hint_match.end), (15, 31));
+
+        let hint_match = dictionary.detect("metal_plate_ao_4k").unwrap();
+        assert_eq!((hint_match.start, hint_match.end), (12, 14));
+    }
+
+    #[test]
+    fn custom_synonyms_take_precedence() {
+        let mut dictionary = HintDictionary::default();
+        dictionary.add("paint", "albedo").unwrap();
+        dictionary.add("Rough Cast", "height").unwrap();
+
+        assert_channels(&dictionary, &[
+            ("wall_paint_roughness", Some("albedo")),
+            ("wall_rough_cast", Some("height")),
+        ]);
+        assert!(dictionary.add("paint", "shininess").is_err());
+    }
+
+    #[test]
+    fn presets_differ_from_the_default() {
+        let default = HintDictionary::default();
+        let preset = HintDictionary::with_preset;
+
+        // ambientCG: the file type is a tag, so "Rock042_4K-PNG_Color" groups with "Rock042_4K_Color"
+        assert!(!default.is_tag("PNG"));
+        assert!(preset(Preset::AmbientCg).is_tag("PNG"));
+
+        // Poliigon: the variation and bit depth belong to the hint, and the workflow suffix is ignored
+        let hint_match = preset(Preset::Poliigon).detect("Wood_COL_VAR1_3K").unwrap();
+        assert_eq!((hint_match.start, hint_match.end), (5, 13));
+        assert_eq!(default.detect("Wood_COL_VAR1_3K").unwrap().end, 8);
+        assert_eq!(preset(Preset::Poliigon).detect("Wood_NRM16_3K").unwrap().end, 10);
+        assert_eq!(default.detect("Wood_NRM16_3K").unwrap().end, 8);
+        assert_channels(&default, &[("Wood_COL_3K_METALNESS", Some("metallic"))]);
+        assert_channels(&preset(Preset::Poliigon), &[("Wood_COL_3K_METALNESS", Some("albedo"))]);
+
+        // Megascans: the level of detail is a tag, so "Rock_4K_Normal_LOD0" groups with "Rock_4K_Albedo"
+        assert!(!default.is_tag("LOD0"));
+        assert!(preset(Preset::Megascans).is_tag("LOD0"));
+
+        // Substance: unlabelled normal maps are DirectX, and "Mixed_AO" is a single hint
+        assert_eq!(default.detect_normal_convention("Plate_Normal"), NormalConvention::OpenGl);
+        assert_eq!(preset(Preset::Substance).detect_normal_convention("Plate_Normal"), NormalConvention::DirectX);
+        assert_eq!(preset(Preset::Substance).detect_normal_convention("Plate_Normal_OpenGL"), NormalConvention::OpenGl);
+        assert_eq!(preset(Preset::Substance).detect("Plate_Mixed_AO").unwrap().start, 6);
+        assert_eq!(default.detect("Plate_Mixed_AO").unwrap().start, 12);
+
+        // Poly Haven names only use built-in synonyms and tags, and its normal maps are OpenGL,
+        // so the preset detects and groups them exactly like the default
+        let span = |dictionary: &HintDictionary, filename: &str| {
+            dictionary.detect(filename).map(|hint_match| (hint_match.channel, hint_match.start, hint_match.end))
+        };
+        for filename in ["rock_wall_diff_4k", "rock_wall_nor_gl_4k", "rock_wall_arm_4k", "rock_wall_disp_4k"] {
+            assert_eq!(span(&preset(Preset::PolyHaven), filename), span(&default, filename), "{}", filename);
+        }
+        assert_eq!(preset(Preset::PolyHaven).normal_convention(), default.normal_convention());
+    }
+}
@@ -0,0 +1,323 @@
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{mpsc, Arc};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+use crate::{read_import_file, texture_property, GodotMaterialProperty, HintDictionary};
+use crate::{MaterialError, MaterialSettings, Texture, TextureChannel};
+use crate::project::resource_path;
+
+/// How often the files are checked without a notification, which also bounds how
+/// long a cancellation takes to be noticed. Some file systems (e.g. network drives)
+/// don't send notifications at all.
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// The number of missing files listed, before the rest is summarized
+const MAX_LISTED: usize = 10;
+
+/// Import wait
+/// How long to wait for Godot to import the converted files, and the flag which
+/// cancels the wait early, e.g. when the user presses Ctrl+C
+#[derive(Debug, Clone)]
+pub struct ImportWait {
+    pub timeout: Duration,
+    pub cancelled: Arc<AtomicBool>,
+}
+
+impl Default for ImportWait {
+    fn default() -> Self {
+        ImportWait {
+            timeout: Duration::from_secs(100),
+            cancelled: Arc::new(AtomicBool::new(false)),
+        }
+    }
+}
+
+/// Wait until Godot has created the .import files of all ``files``
+///
+/// Godot doesn't import new files right away. Usually, the user has to focus the Godot
+/// window first. The directories are watched for changes, so the wait ends as soon as
+/// the last file is imported. The files which are still missing are listed whenever
+/// some of them show up.
+///
+/// Fails with the missing files when the timeout passes, or the wait is cancelled.
+pub fn wait_for_import_files(files: &[PathBuf], wait: &ImportWait) -> Result<(), MaterialError> {
+    let mut missing: Vec<PathBuf> = files
+        .iter()
+        .map(|file| import_path_for(file))
+        .filter(|import_path| !is_imported(import_path))
+        .collect();
+
+    if missing.is_empty() {
+        return Ok(());
+    }
+
+    // The watcher has to stay alive while waiting
+    let (sender, receiver) = mpsc::channel::<()>();
+    let _watcher = watch(&missing, sender);
+
+    println!("Waiting for {} of {} .import files. \
+              Make the Godot window active, which prompts it to import the files (Ctrl+C cancels):",
+             missing.len(),
+             files.len());
+    print_missing(&missing);
+
+    let deadline = Instant::now() + wait.timeout;
+
+    loop {
+        if wait.cancelled.load(Ordering::SeqCst) {
+            return Err(MaterialError::ImportCancelled { missing });
+        }
+
+        let now = Instant::now();
+        if now >= deadline {
+            return Err(MaterialError::ImportTimeout { missing });
+        }
+
+        // Sleep until something changes in the watched directories, or it's time to poll.
+        // A burst of notifications is handled at once.
+        let _ = receiver.recv_timeout(POLL_INTERVAL.min(deadline - now));
+        while receiver.try_recv().is_ok() {}
+
+        let still_missing = missing.len();
+        missing.retain(|import_path| !is_imported(import_path));
+
+        if missing.is_empty() {
+            println!("All .import files found");
+            return Ok(());
+        }
+
+        if missing.len() < still_missing {
+            println!("{} of {} .import files found, still waiting for:", files.len() - missing.len(), files.len());
+            print_missing(&missing);
+        }
+    }
+}
+
+/// Write the .import files of the textures of a material, with the import settings the
+/// material needs, so Godot doesn't have to be told in its import dock
+///
+/// * All textures are VRAM compressed, with mipmaps, as Godot does for 3D textures
+/// * Normal maps are compressed as normal maps
+/// * Roughness (and ORM) textures are linked to the normal map of the material, if
+///   there's one, which reduces specular aliasing
+/// * Albedo textures have the color of their opaque pixels bled into the transparent ones
+///
+/// Textures without a channel hint only get the settings shared by all textures.
+///
+/// The textures have to be inside a Godot project. The UID is derived from the res:// path,
+/// so it stays the same from one run to the next. Existing .import files keep their UID,
+/// and are only replaced when ``overwrite`` is set. Returns the .import files written.
+pub fn write_import_files(
+    textures: &[Texture],
+    dictionary: &HintDictionary,
+    settings: &MaterialSettings,
+    overwrite: bool,
+) -> Result<Vec<PathBuf>, MaterialError> {
+    let normal_map: Option<String> = textures
+        .iter()
+        .find(|texture| texture_property(texture, dictionary) == Some(GodotMaterialProperty::NormalTexture))
+        .map(|texture| resource_path(&texture.path))
+        .transpose()?;
+
+    let mut written: Vec<PathBuf> = Vec::new();
+
+    for texture in textures {
+        let import_file = import_path_for(&texture.path);
+        let exists = import_file.exists();
+
+        if exists && !overwrite {
+            continue;
+        }
+
+        let property = texture_property(texture, dictionary);
+        let source_file = resource_path(&texture.path)?;
+
+        // Materials may already refer to the texture by the UID of the existing file
+        let uid = match exists {
+            true => read_import_file(&import_file).ok().and_then(|(uid, _)| uid),
+            false => None,
+        };
+        let uid = uid.unwrap_or_else(|| stable_uid(&source_file));
+
+        let roughness_channel: Option<TextureChannel> = match property {
+            Some(GodotMaterialProperty::RoughnessTexture) => Some(texture.channel
+                .or_else(|| settings.texture_channels.get("roughness").copied())
+                .unwrap_or(TextureChannel::Red)),
+            Some(GodotMaterialProperty::OrmTexture) => Some(TextureChannel::Green),
+            _ => None,
+        };
+
+        // Godot's roughness modes are "Detect" and "Disabled", followed by the channels
+        let (roughness_mode, src_normal) = match (roughness_channel, &normal_map) {
+            (Some(channel), Some(normal_map)) => (channel.godot_value() + 2, normal_map.as_str()),
+            _ => (0, ""),
+        };
+
+        let data = format!(
+            "[remap]\n\n\
+             importer=\"texture\"\n\
+             type=\"CompressedTexture2D\"\n\
+             uid=\"uid://{uid}\"\n\n\
+             [deps]\n\n\
+             source_file=\"{source_file}\"\n\n\
+             [params]\n\n\
+             compress/mode=2\n\
+             compress/high_quality=false\n\
+             compress/lossy_quality=0.7\n\
+             compress/hdr_compression=1\n\
+             compress/normal_map={normal_map_mode}\n\
+             compress/channel_pack=0\n\
+             mipmaps/generate=true\n\
+             mipmaps/limit=-1\n\
+             roughness/mode={roughness_mode}\n\
+             roughness/src_normal=\"{src_normal}\"\n\
+             process/fix_alpha_border={fix_alpha_border}\n\
+             process/premult_alpha=false\n\
+             process/normal_map_invert_y=false\n\
+             process/hdr_as_srgb=false\n\
+             process/hdr_clamp_exposure=false\n\
+             process/size_limit=0\n\
+             detect_3d/compress_to=0\n",
+            // Godot's normal map modes are "Detect", "Enable" and "Disabled"
+            normal_map_mode = if property == Some(GodotMaterialProperty::NormalTexture) { 1 } else { 0 },
+            fix_alpha_border = property == Some(GodotMaterialProperty::AlbedoTexture),
+        );
+
+        fs::write(&import_file, data).map_err(|err| MaterialError::ImportWrite {
+            path: import_file.clone(),
+            reason: err.to_string(),
+        })?;
+        written.push(import_file);
+    }
+
+    Ok(written)
+}
+
+/// A UID derived from the res:// path of a texture
+///
+/// The path is hashed with FNV-1a, which, unlike the hasher of the standard library, is
+/// guaranteed to give the same result with every build.
+fn stable_uid(source_file: &str) -> String {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for byte in source_file.bytes() {
+        hash ^= u64::from(byte);
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+    }
+
+    // Godot's UIDs are positive 63-bit numbers
+    id_to_text(hash >> 1)
+}
+
+/// Write a UID the way Godot's ``ResourceUID::id_to_text`` does
+/// The base is 34: the letters "a" to "y", followed by the digits "0" to "8".
+pub(crate) fn id_to_text(mut id: u64) -> String {
+    const LETTERS: u64 = (b'z' - b'a') as u64;
+    const BASE: u64 = LETTERS + (b'9' - b'0') as u64;
+
+    let mut digits: Vec<u8> = Vec::new();
+
+    while id > 0 {
+        let digit = id % BASE;
+        digits.push(match digit < LETTERS {
+            true => b'a' + digit as u8,
+            false => b'0' + (digit - LETTERS) as u8,
+        });
+        id /= BASE;
+    }
+
+    digits.reverse();
+    String::from_utf8(digits).expect("The digits are ASCII")
+}
+
+/// The path of the .import file Godot creates next to ``file``
+/// For instance "albedo.png" is paired with "albedo.png.import"
+pub fn import_path_for(file: &Path) -> PathBuf {
+    let mut import_path = file.as_os_str().to_owned();
+    import_path.push(".import");
+    PathBuf::from(import_path)
+}
+
+/// Whether Godot has finished writing the .import file
+/// The source file is listed after the UID, so a file which is still being written doesn't count.
+fn is_imported(import_path: &Path) -> bool {
+    fs::read_to_string(import_path).is_ok_and(|data| data.contains("source_file="))
+}
+
+/// Watch the directories the .import files are created in, and signal every change
+///
+/// Returns ``None`` when the directories can't be watched, in which case the wait
+/// relies on polling alone.
+fn watch(import_paths: &[PathBuf], sender: mpsc::Sender<()>) -> Option<RecommendedWatcher> {
+    let mut watcher = notify::recommended_watcher(move |_event: notify::Result<notify::Event>| {
+        let _ = sender.send(());
+    }).ok()?;
+
+    let directories: HashSet<&Path> = import_paths
+        .iter()
+        .map(|import_path| match import_path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new("."),
+        })
+        .collect();
+
+    for directory in directories {
+        watcher.watch(directory, RecursiveMode::NonRecursive).ok()?;
+    }
+
+    Some(watcher)
+}
+
+/// List the missing .import files, leaving out the rest of a long list
+fn print_missing(missing: &[PathBuf]) {
+    for import_path in missing.iter().take(MAX_LISTED) {
+        println!("  {}", import_path.display());
+    }
+
+    if missing.len() > MAX_LISTED {
+        println!("  ... and {} more", missing.len() - MAX_LISTED);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Read a UID the way Godot's ``ResourceUID::text_to_id`` does
+    fn text_to_id(text: &str) -> u64 {
+        text.bytes().fold(0, |id, c| match c {
+            b'a'..=b'y' => id * 34 + u64::from(c - b'a'),
+            b'0'..=b'8' => id * 34 + u64::from(c - b'0') + 25,
+            _ => panic!("Invalid UID digit: {}", c as char),
+        })
+    }
+
+    #[test]
+    fn id_to_text_uses_godot_digits() {
+        assert_eq!(id_to_text(1), "b");
+        assert_eq!(id_to_text(24), "y");
+        assert_eq!(id_to_text(25), "0");
+        assert_eq!(id_to_text(33), "8");
+        assert_eq!(id_to_text(34), "ba");
+        assert_eq!(id_to_text(34 * 34 - 1), "88");
+    }
+
+    #[test]
+    fn id_to_text_round_trips() {
+        for id in [1, 35, 1_000_000, u64::MAX >> 1] {
+            assert_eq!(text_to_id(&id_to_text(id)), id);
+        }
+    }
+
+    #[test]
+    fn stable_uid_is_deterministic() {
+        let uid = stable_uid("res://textures/rock_albedo.png");
+
+        assert_eq!(uid, stable_uid("res://textures/rock_albedo.png"));
+        assert_ne!(uid, stable_uid("res://textures/rock_normal.png"));
+        assert!(uid.bytes().all(|c| matches!(c, b'a'..=b'y' | b'0'..=b'8')));
+        assert!(text_to_id(&uid) <= i64::MAX as u64);
+    }
+}
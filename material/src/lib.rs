@@ -1,207 +1,366 @@
-use std::{io, thread};
+mod hints;
+mod import;
+mod presets;
+mod project;
+mod settings;
+
+use std::fmt;
 use std::fs::File;
-use std::io::{Read, Write};
-use std::path::PathBuf;
-use std::time::Duration;
+use std::io::Read;
+use std::path::{Path, PathBuf};
 use rand::distributions::{Alphanumeric, DistString};
-use rand::thread_rng;
+use rand::{thread_rng, Rng};
 use regex::Regex;
 
+pub use hints::{HintDictionary, HintMatch};
+pub use import::{import_path_for, wait_for_import_files, write_import_files, ImportWait};
+pub use presets::{NormalConvention, Preset};
+pub use settings::{Color, CullMode, MaterialSettings, TextureChannel, Transparency};
+
 /// Godot Material Property
 /// Contains the supported material property types such as albedo, normal map and roughness
+#[allow(clippy::enum_variant_names)]
+#[derive(PartialEq, Eq)]
 enum GodotMaterialProperty {
     AlbedoTexture,
     NormalTexture,
     HeightTexture,
     RoughnessTexture,
     MetallicTexture,
-    AmbientOcclusionTexture
+    AmbientOcclusionTexture,
+    EmissionTexture,
+    /// Occlusion, roughness and metallic packed into the red, green and blue channels
+    OrmTexture,
+}
+
+impl GodotMaterialProperty {
+    /// The hint which maps to this property
+    fn hint(&self) -> &'static str {
+        match self {
+            GodotMaterialProperty::AlbedoTexture => "albedo",
+            GodotMaterialProperty::NormalTexture => "normal",
+            GodotMaterialProperty::HeightTexture => "height",
+            GodotMaterialProperty::RoughnessTexture => "roughness",
+            GodotMaterialProperty::MetallicTexture => "metallic",
+            GodotMaterialProperty::AmbientOcclusionTexture => "ao",
+            GodotMaterialProperty::EmissionTexture => "emission",
+            GodotMaterialProperty::OrmTexture => "orm",
+        }
+    }
+
+    /// Whether the property is one of those an ORM texture packs together
+    fn is_packed_in_orm(&self) -> bool {
+        matches!(self, GodotMaterialProperty::AmbientOcclusionTexture
+            | GodotMaterialProperty::RoughnessTexture
+            | GodotMaterialProperty::MetallicTexture)
+    }
+}
+
+/// Godot material type
+/// ``ORMMaterial3D`` reads occlusion, roughness and metallic from a single texture,
+/// while ``StandardMaterial3D`` has a texture slot for each of them
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum GodotMaterialType {
+    Standard,
+    Orm,
+}
+
+impl GodotMaterialType {
+    /// The resource type written in the material file
+    fn resource_type(&self) -> &'static str {
+        match self {
+            GodotMaterialType::Standard => "StandardMaterial3D",
+            GodotMaterialType::Orm => "ORMMaterial3D",
+        }
+    }
+}
+
+/// Material error
+/// The reasons why generating a material can fail, carrying the context
+/// needed to tell the user which file is the culprit
+#[derive(Debug)]
+pub enum MaterialError {
+    /// Godot didn't create the .import files in time
+    ImportTimeout { missing: Vec<PathBuf> },
+    /// The wait for the .import files was cancelled
+    ImportCancelled { missing: Vec<PathBuf> },
+    /// An .import file couldn't be read, or lacks the UID or source file
+    ImportParse { path: PathBuf, reason: String },
+    /// The filename doesn't contain any of the supported hints
+    UnknownProperty { path: PathBuf },
+    /// The texture isn't inside a Godot project, so it has no res:// path
+    NoProject { path: PathBuf },
+    /// An .import file couldn't be written
+    ImportWrite { path: PathBuf, reason: String },
+}
+
+impl fmt::Display for MaterialError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MaterialError::ImportTimeout { missing } => {
+                write!(f, "Will not wait any longer for .import files, missing: {}", file_names(missing))
+            },
+            MaterialError::ImportCancelled { missing } => {
+                write!(f, "Stopped waiting for .import files, missing: {}", file_names(missing))
+            },
+            MaterialError::ImportParse { path, reason } => {
+                write!(f, "Failed to parse {}: {}", path.display(), reason)
+            },
+            MaterialError::UnknownProperty { path } => {
+                write!(f, "No material channel hint found in: {}", path.display())
+            },
+            MaterialError::NoProject { path } => {
+                write!(f, "No project.godot found in the directories above: {}", path.display())
+            },
+            MaterialError::ImportWrite { path, reason } => {
+                write!(f, "Failed to write {}: {}", path.display(), reason)
+            },
+        }
+    }
+}
+
+/// The names of the files, separated by commas
+fn file_names(paths: &[PathBuf]) -> String {
+    paths
+        .iter()
+        .map(|path| path.file_name().unwrap_or_default().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Alpha
+/// The kind of transparency found in the alpha channel of a texture
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Alpha {
+    /// Every pixel is opaque, or there's no alpha channel
+    #[default]
+    Opaque,
+    /// Pixels are either opaque or transparent, apart from soft edges
+    Cutout,
+    /// Large parts are partially transparent
+    Translucent,
+}
+
+/// Texture reference
+/// How the material refers to its textures
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TextureReference {
+    /// By the UID and path from the .import file Godot creates when it imports the texture
+    Uid,
+    /// By the res:// path, computed from the location of the ``project.godot`` file.
+    /// Godot fills in the UIDs once it has imported the textures.
+    Path,
+}
+
+/// Texture
+/// A converted file which should be part of the material. The property it maps to
+/// is detected from the filename, unless an explicit hint (such as "albedo") is given.
+#[derive(Debug, Clone)]
+pub struct Texture {
+    pub path: PathBuf,
+    pub hint: Option<String>,
+    /// The channel of the texture the property reads, overriding the material settings
+    pub channel: Option<TextureChannel>,
+    /// The transparency found in the texture, which is only considered for albedo textures
+    pub alpha: Alpha,
 }
 
 /// Godot material mapping
 /// A result-type object which contains all information relevant to generate
 /// a Godot material such as source files, property type and UID
 struct GodotMaterialMapping {
-    uid: String,
+    /// Unknown until Godot imports the texture, when referencing textures by path
+    uid: Option<String>,
     short_uid: String,
     source_file: String,
     property: GodotMaterialProperty,
+    channel: Option<TextureChannel>,
+    alpha: Alpha,
 }
 
-/// Generate a ``StandardMaterial3D`` based on the files that have been converted
+/// Generate a ``StandardMaterial3D`` (or ``ORMMaterial3D``) based on the files that have been converted
 /// A requirement for this to work is that the files contain hints in their names
 /// such as "albedo" or "normal"
 ///
-/// Currently supported hints:
-/// * albedo
-/// * normal
-/// * height
-/// * roughness
-/// * metallic
-/// * ao (Ambient Occlusion)
-pub fn generate(files: Vec<PathBuf>) -> Result<String, String> {
-    let files_found = scan_for_import_files(&files);
-
-    // Abort, if the number of .import files doesn't match number of converted files
-    // This means Godot hasn't seen all files yet, or has failed to import some
-    // In this case, we don't want to just go ahead and create the material
-    if files_found.len() < files.len() {
-        return Err(String::from("Will not wait any longer for .import files"));
-    }
-
+/// Supported channels, each recognized by a number of synonyms (see ``HintDictionary``):
+/// * albedo (e.g. "Color", "BaseColor", "Diffuse")
+/// * normal (e.g. "NormalGL", "nrm")
+/// * height (e.g. "Displacement", "disp")
+/// * roughness (e.g. "Rough")
+/// * metallic (e.g. "Metalness")
+/// * ao (Ambient Occlusion, e.g. "AmbientOcclusion")
+/// * emission (e.g. "Emissive", "Glow")
+/// * orm (occlusion, roughness and metallic packed in one texture, e.g. "ORM" or "ARM")
+///
+/// When an ORM texture is found, an ``ORMMaterial3D`` is generated, unless there
+/// are separate occlusion, roughness or metallic textures as well. In that case
+/// a ``StandardMaterial3D`` is generated, which reads the channels that aren't
+/// covered by the separate textures from the ORM texture.
+///
+/// The channel can also be given explicitly on a ``Texture``, in which case
+/// the filename isn't consulted.
+///
+/// Values which can't be derived from the textures, such as the emission color,
+/// are taken from the ``settings``. When the albedo texture has transparent pixels,
+/// the material is made transparent (see ``Alpha``).
+///
+/// When referencing the textures by UID, the .import files Godot creates for them have to
+/// exist, see ``wait_for_import_files``. When referencing them by path, they have to be
+/// inside a Godot project.
+pub fn generate(
+    textures: Vec<Texture>,
+    dictionary: &HintDictionary,
+    settings: &MaterialSettings,
+    reference: TextureReference,
+) -> Result<String, MaterialError> {
     // Create the list of materials discovered
-    let uid_mapping = compile_material_mapping(&files_found);
-
-    // The number of discovered materials must match the number of files
-    // Otherwise, similarly to above, we risk creating a material with missing
-    // properties and attributes
-    if uid_mapping.len() != files_found.len() {
-        return Err(String::from("UID mapping does not match number of files."));
-    }
+    // Every .import file must produce a mapping, otherwise we risk creating
+    // a material with missing properties and attributes
+    let uid_mapping = compile_material_mapping(&textures, dictionary, reference)?;
 
     // Generate the data and save the material file
-    Ok(generate_material(&uid_mapping))
+    Ok(generate_material(&uid_mapping, settings))
 }
 
 /// This method generates the data of the material (.tres) file, using a
 /// series private helper functions.
 ///
 /// Lastly, it saves the file - or dies trying.
-fn generate_material(mapping: &Vec<GodotMaterialMapping>) -> String {
+fn generate_material(mapping: &Vec<GodotMaterialMapping>, settings: &MaterialSettings) -> String {
     let mut mat_data = String::new();
+    let material_type = get_godot_material_type(mapping);
 
-    generate_header(&mut mat_data);
+    generate_header(&mut mat_data, material_type);
     generate_ext_resources(&mut mat_data, mapping);
-    generate_resources(&mut mat_data, mapping);
+    generate_resources(&mut mat_data, mapping, material_type, settings);
 
     mat_data
 }
 
-/// In order to generate the Godot material, we need to peek into the .import
-/// files which Godot creates in pairs with media files such as images.
-/// The .import files contain vital information such as the resource path and UID
-///
-/// We keep scanning (using thread sleep) because Godot doesn't immediately detect
-/// new files and create the .import pairs. In fact, the user will usually have to
-/// focus on the Godot window.
-///
-/// Once all files are found, we exit the loop and return the list
-fn scan_for_import_files(files: &Vec<PathBuf>) -> Vec<PathBuf> {
-    let mut files_found: Vec<PathBuf>;
-    let mut iters: i8 = 0;
-    let max_iters: i8 = 100;
-
-    loop {
-        files_found = Vec::new();
-
-        // Loop over the converted files, and see if there exists an pairing
-        // with the extension .import
-        for f in files {
-            let ext = f.extension().unwrap().to_str().unwrap();
-            let import_path = f.clone().with_extension(format!("{}.import", ext));
-            if import_path.exists() {
-                files_found.push(import_path);
-            }
-        }
-
-        // If the number of .import files doesn't match the number of converted files,
-        // we will put the thread to sleep for a second, and then try again
-        if files_found.len() < files.len() {
-            if iters == 0 {
-                print!("{} {}",
-                       "Waiting for .import files.",
-                       "Make Godot window active. This will prompt it to create the .import files: .",
-                );
-            } else {
-                print!(".");
-            }
-
-            io::stdout().flush().unwrap();
-            thread::sleep(Duration::from_secs(1));
-        }
-
-        iters += 1;
+/// An ``ORMMaterial3D`` is used when there's an ORM texture, and nothing which
+/// would need the separate texture slots of a ``StandardMaterial3D``
+fn get_godot_material_type(mapping: &[GodotMaterialMapping]) -> GodotMaterialType {
+    let has_orm = mapping.iter().any(|m| m.property == GodotMaterialProperty::OrmTexture);
+    let has_separate = mapping.iter().any(|m| m.property.is_packed_in_orm());
 
-        // If max iterations (time-out) is reacted, or if sufficient .import
-        // files have been discovered, we break the loop
-        if iters >= max_iters || files_found.len() == files.len() {
-            break;
-        }
+    match has_orm && !has_separate {
+        true => GodotMaterialType::Orm,
+        false => GodotMaterialType::Standard,
     }
-
-    files_found
 }
 
 /// Look through the contents of the .import files in order to extract the resources'
-/// UID, local path, etc.
-fn compile_material_mapping(files_found: &Vec<PathBuf>) -> Vec<GodotMaterialMapping> {
+/// UID, local path, etc. When referencing the textures by path, the path is computed
+/// instead, and the UID is only taken from .import files which already exist.
+fn compile_material_mapping(
+    textures: &[Texture],
+    dictionary: &HintDictionary,
+    reference: TextureReference,
+) -> Result<Vec<GodotMaterialMapping>, MaterialError> {
     let mut uid_mapping: Vec<GodotMaterialMapping> = Vec::new();
 
+    for texture in textures {
+        let import_file = &import_path_for(&texture.path);
+
+        let property = texture_property(texture, dictionary)
+            .ok_or_else(|| MaterialError::UnknownProperty { path: import_file.clone() })?;
+
+        let (uid, source_file) = match reference {
+            TextureReference::Uid => match read_import_file(import_file)? {
+                (Some(uid), Some(source_file)) => (Some(uid), source_file),
+                (None, _) => return Err(MaterialError::ImportParse {
+                    path: import_file.clone(),
+                    reason: String::from("missing uid"),
+                }),
+                (_, None) => return Err(MaterialError::ImportParse {
+                    path: import_file.clone(),
+                    reason: String::from("missing source_file"),
+                }),
+            },
+            TextureReference::Path => {
+                let uid = match import_file.exists() {
+                    true => read_import_file(import_file)?.0,
+                    false => None,
+                };
+                (uid, project::resource_path(&texture.path)?)
+            },
+        };
+
+        uid_mapping.push(GodotMaterialMapping {
+            property,
+            channel: texture.channel,
+            alpha: texture.alpha,
+            uid,
+            source_file,
+            short_uid: format!("{}_{}", uid_mapping.len() + 1, generate_godot_uid(5)),
+        });
+    }
+
+    Ok(uid_mapping)
+}
+
+/// Extract the UID and the source file (the res:// path) from an .import file
+pub(crate) fn read_import_file(import_file: &Path) -> Result<(Option<String>, Option<String>), MaterialError> {
     // Set up the two regular expressions used to extract UID and source_file properties
     let uid_regex = Regex::new(r#"\buid="uid://([^"]+)""#).unwrap();
     let sf_regex = Regex::new(r#"\bsource_file="(res://[^"]+)""#).unwrap();
 
-    // Iterate through the discovered .import files
-    for import_file in files_found {
-        let mut file = File::open(import_file).expect("Failed to open import file");
-        let mut data = String::new();
-
-        let mut uid: Option<String> = None;
-        let mut source_file: Option<String> = None;
-
-        // Load the file contents into the file variable
-        file.read_to_string(&mut data).expect("Failed to read lines from import file");
-
-        for line in data.lines() {
-            // If the line matches the UID property, we extract the value
-            if let Some(captures) = uid_regex.captures(&line) {
-                if let Some(value) = captures.get(1).map(|m| m.as_str()) {
-                    uid = Some(value.to_owned());
-                }
-            }
-
-            // If the line matches source_file, we extract the value
-            if let Some(captures) = sf_regex.captures(&line) {
-                if let Some(value) = captures.get(1).map(|m| m.as_str()) {
-                    source_file = Some(value.to_owned());
-                }
+    let mut data = String::new();
+    let mut uid: Option<String> = None;
+    let mut source_file: Option<String> = None;
+
+    // Load the file contents into the data variable
+    File::open(import_file)
+        .and_then(|mut file| file.read_to_string(&mut data))
+        .map_err(|err| MaterialError::ImportParse {
+            path: import_file.to_path_buf(),
+            reason: err.to_string(),
+        })?;
+
+    for line in data.lines() {
+        // If the line matches the UID property, we extract the value
+        if let Some(captures) = uid_regex.captures(line) {
+            if let Some(value) = captures.get(1).map(|m| m.as_str()) {
+                uid = Some(value.to_owned());
             }
         }
 
-        // Figure out which (if any) property the filename maps to
-        // For instance if it contains "albedo" it maps to the AlbedoTexture property
-        let property: Option<GodotMaterialProperty> = get_godot_property(import_file);
-        if uid.is_some() && source_file.is_some() {
-            uid_mapping.push(GodotMaterialMapping {
-                property: property.unwrap(),
-                uid: uid.unwrap(),
-                source_file: source_file.unwrap(),
-                short_uid: format!("{}_{}", uid_mapping.len() + 1, generate_godot_uid(5)),
-            });
+        // If the line matches source_file, we extract the value
+        if let Some(captures) = sf_regex.captures(line) {
+            if let Some(value) = captures.get(1).map(|m| m.as_str()) {
+                source_file = Some(value.to_owned());
+            }
         }
     }
 
-    uid_mapping
+    Ok((uid, source_file))
 }
 
 /// Generate the first line in the Godot material file
-fn generate_header(mat_data: &mut String) {
+/// The UID is random, like those Godot creates: a positive 63-bit number.
+fn generate_header(mat_data: &mut String, material_type: GodotMaterialType) {
     mat_data.push_str(
-        format!("[gd_resource type=\"StandardMaterial3D\" format=3 uid=\"uid://{}\"]\n\n",
-                generate_godot_uid(12)
+        format!("[gd_resource type=\"{}\" format=3 uid=\"uid://{}\"]\n\n",
+                material_type.resource_type(),
+                import::id_to_text(thread_rng().gen_range(1..=i64::MAX as u64))
         ).as_str()
     );
 }
 
 /// Generate the ext_resource tags for the material file
 /// The ext_resource are references to the .import files
-/// They are assigned an arbitrary "short uid"
+/// They are assigned an arbitrary "short uid". Without a UID, Godot finds the texture by path.
 fn generate_ext_resources(mat_data: &mut String, uid_mapping: &Vec<GodotMaterialMapping>) {
     for res in uid_mapping {
+        let uid = match &res.uid {
+            Some(uid) => format!(" uid=\"uid://{}\"", uid),
+            None => String::new(),
+        };
+
         mat_data.push_str(format!(
-            "[ext_resource type=\"Texture2D\" path=\"{}\" uid=\"uid://{}\" id=\"{}\"]\n",
+            "[ext_resource type=\"Texture2D\" path=\"{}\"{} id=\"{}\"]\n",
             res.source_file,
-            res.uid,
+            uid,
             res.short_uid
         ).as_str());
     }
@@ -209,7 +368,12 @@ fn generate_ext_resources(mat_data: &mut String, uid_mapping: &Vec<GodotMaterial
 
 /// Generate the [resource] tag in the material file
 /// This mainly consists of bool, numeric values and references to the ``ext_resource``s.
-fn generate_resources(mat_data: &mut String, uid_mapping: &Vec<GodotMaterialMapping>) {
+fn generate_resources(
+    mat_data: &mut String,
+    uid_mapping: &Vec<GodotMaterialMapping>,
+    material_type: GodotMaterialType,
+    settings: &MaterialSettings,
+) {
     mat_data.push_str("\n[resource]");
 
     for prop in uid_mapping {
@@ -219,6 +383,7 @@ fn generate_resources(mat_data: &mut String, uid_mapping: &Vec<GodotMaterialMapp
                     "\nalbedo_texture = ExtResource(\"{}\")",
                     prop.short_uid).as_str()
                 );
+                generate_transparency(mat_data, prop.alpha, settings);
             },
             GodotMaterialProperty::NormalTexture => {
                 mat_data.push_str("\nnormal_enabled = true");
@@ -232,6 +397,7 @@ fn generate_resources(mat_data: &mut String, uid_mapping: &Vec<GodotMaterialMapp
                     "\nroughness_texture = ExtResource(\"{}\")",
                     prop.short_uid).as_str()
                 );
+                generate_texture_channel(mat_data, prop, settings, "roughness");
             },
             GodotMaterialProperty::HeightTexture => {
                 mat_data.push_str("\nheightmap_enabled = true");
@@ -246,6 +412,7 @@ fn generate_resources(mat_data: &mut String, uid_mapping: &Vec<GodotMaterialMapp
                     "\nmetallic_texture = ExtResource(\"{}\")",
                     prop.short_uid).as_str()
                 );
+                generate_texture_channel(mat_data, prop, settings, "metallic");
             },
             GodotMaterialProperty::AmbientOcclusionTexture => {
                 mat_data.push_str("\nao_enabled = true");
@@ -253,46 +420,318 @@ fn generate_resources(mat_data: &mut String, uid_mapping: &Vec<GodotMaterialMapp
                     "\nao_texture = ExtResource(\"{}\")",
                     prop.short_uid).as_str()
                 );
+                generate_texture_channel(mat_data, prop, settings, "ao");
+            },
+            GodotMaterialProperty::EmissionTexture => {
+                // The default emission color is black, which would hide the texture
+                mat_data.push_str("\nemission_enabled = true");
+                mat_data.push_str(format!("\nemission = {}", settings.emission_color).as_str());
+                mat_data.push_str(format!(
+                    "\nemission_energy_multiplier = {:?}",
+                    settings.emission_energy).as_str()
+                );
+                mat_data.push_str(format!(
+                    "\nemission_texture = ExtResource(\"{}\")",
+                    prop.short_uid).as_str()
+                );
+            },
+            GodotMaterialProperty::OrmTexture if material_type == GodotMaterialType::Orm => {
+                // Occlusion and metallic have no effect, unless they're enabled
+                mat_data.push_str("\nao_enabled = true");
+                mat_data.push_str("\nmetallic = 1.0");
+                mat_data.push_str(format!(
+                    "\norm_texture = ExtResource(\"{}\")",
+                    prop.short_uid).as_str()
+                );
+            },
+            GodotMaterialProperty::OrmTexture => {
+                generate_orm_fallback(mat_data, uid_mapping, &prop.short_uid);
             },
         }
     }
 }
 
-/// Based on the filename, this function will return which ``GodotMaterialProperty``
-/// is a fitting choice
+/// Write the transparency and cull mode of the material, based on the alpha of the albedo texture
 ///
-/// If no choice is made, it returns ``None``.
-fn get_godot_property(path: &PathBuf) -> Option<GodotMaterialProperty> {
-    let filename = path.file_name().unwrap().to_str().unwrap();
+/// Cutouts, such as foliage and fences, use alpha scissor, and translucent surfaces use alpha
+/// blending, unless the settings pick the transparency. Transparent materials are rendered
+/// from both sides, unless the settings pick the cull mode.
+fn generate_transparency(mat_data: &mut String, alpha: Alpha, settings: &MaterialSettings) {
+    let transparency = match (alpha, settings.transparency) {
+        (Alpha::Opaque, _) => Transparency::Disabled,
+        (_, Some(transparency)) => transparency,
+        (Alpha::Cutout, None) => Transparency::Scissor,
+        (Alpha::Translucent, None) => Transparency::Blend,
+    };
+
+    if transparency != Transparency::Disabled {
+        mat_data.push_str(format!("\ntransparency = {}", transparency.godot_value()).as_str());
+    }
 
-    if filename.contains("albedo") {
-        return Some(GodotMaterialProperty::AlbedoTexture);
+    if transparency == Transparency::Scissor {
+        mat_data.push_str(format!(
+            "\nalpha_scissor_threshold = {:?}",
+            settings.alpha_scissor_threshold).as_str()
+        );
     }
 
-    if filename.contains("normal") {
-        return Some(GodotMaterialProperty::NormalTexture);
+    let cull_mode = match (settings.cull_mode, transparency) {
+        (Some(cull_mode), _) => Some(cull_mode),
+        (None, Transparency::Disabled) => None,
+        (None, _) => Some(CullMode::Disabled),
+    };
+
+    if let Some(cull_mode) = cull_mode {
+        mat_data.push_str(format!("\ncull_mode = {}", cull_mode.godot_value()).as_str());
     }
+}
 
-    if filename.contains("height") {
-        return Some(GodotMaterialProperty::HeightTexture);
+/// Write which channel of its texture a property reads, if one is chosen for the
+/// texture itself or for the property in the settings. Otherwise, Godot reads red.
+fn generate_texture_channel(
+    mat_data: &mut String,
+    prop: &GodotMaterialMapping,
+    settings: &MaterialSettings,
+    name: &str,
+) {
+    let channel = prop.channel.or_else(|| settings.texture_channels.get(prop.property.hint()).copied());
+
+    if let Some(channel) = channel {
+        mat_data.push_str(format!("\n{}_texture_channel = {}", name, channel.godot_value()).as_str());
     }
+}
 
-    if filename.contains("roughness") {
-        return Some(GodotMaterialProperty::RoughnessTexture);
+/// Point the occlusion, roughness and metallic slots of a ``StandardMaterial3D`` at
+/// the channels of an ORM texture, except those which have a separate texture
+fn generate_orm_fallback(mat_data: &mut String, uid_mapping: &[GodotMaterialMapping], short_uid: &str) {
+    let has_property = |property: GodotMaterialProperty| {
+        uid_mapping.iter().any(|m| m.property == property)
+    };
+
+    if !has_property(GodotMaterialProperty::AmbientOcclusionTexture) {
+        mat_data.push_str("\nao_enabled = true");
+        mat_data.push_str(format!("\nao_texture = ExtResource(\"{}\")", short_uid).as_str());
+        mat_data.push_str(format!("\nao_texture_channel = {}", TextureChannel::Red.godot_value()).as_str());
     }
 
-    if filename.contains("metallic") {
-        return Some(GodotMaterialProperty::MetallicTexture);
+    if !has_property(GodotMaterialProperty::RoughnessTexture) {
+        mat_data.push_str(format!("\nroughness_texture = ExtResource(\"{}\")", short_uid).as_str());
+        mat_data.push_str(format!(
+            "\nroughness_texture_channel = {}",
+            TextureChannel::Green.godot_value()).as_str()
+        );
     }
 
-    if filename.contains("_ao") {
-        return Some(GodotMaterialProperty::AmbientOcclusionTexture);
+    if !has_property(GodotMaterialProperty::MetallicTexture) {
+        mat_data.push_str("\nmetallic = 1.0");
+        mat_data.push_str(format!("\nmetallic_texture = ExtResource(\"{}\")", short_uid).as_str());
+        mat_data.push_str(format!(
+            "\nmetallic_texture_channel = {}",
+            TextureChannel::Blue.godot_value()).as_str()
+        );
     }
+}
+
+/// Whether a material property exists for the channel, such as "albedo" or "orm".
+/// Channels such as "gloss" are recognized in filenames, but have to be converted first.
+pub fn supports_channel(channel: &str) -> bool {
+    get_godot_property_from_hint(channel).is_some()
+}
 
-    None
+/// Figure out which (if any) property the hint or filename of the texture maps to
+/// For instance if it contains "albedo" it maps to the AlbedoTexture property
+fn texture_property(texture: &Texture, dictionary: &HintDictionary) -> Option<GodotMaterialProperty> {
+    match &texture.hint {
+        Some(hint) => get_godot_property_from_hint(hint),
+        None => get_godot_property(&texture.path, dictionary),
+    }
 }
 
-/// Generate the random Godot-like UID
+/// Based on the filename, this function will return which ``GodotMaterialProperty``
+/// is a fitting choice
+///
+/// If no choice is made, it returns ``None``.
+fn get_godot_property(path: &Path, dictionary: &HintDictionary) -> Option<GodotMaterialProperty> {
+    dictionary.property(path.file_stem()?.to_str()?)
+}
+
+/// Translate an explicit hint, such as "albedo" or "ao", into its ``GodotMaterialProperty``
+fn get_godot_property_from_hint(hint: &str) -> Option<GodotMaterialProperty> {
+    match hint.to_lowercase().as_str() {
+        "albedo" => Some(GodotMaterialProperty::AlbedoTexture),
+        "normal" => Some(GodotMaterialProperty::NormalTexture),
+        "height" => Some(GodotMaterialProperty::HeightTexture),
+        "roughness" => Some(GodotMaterialProperty::RoughnessTexture),
+        "metallic" => Some(GodotMaterialProperty::MetallicTexture),
+        "ao" => Some(GodotMaterialProperty::AmbientOcclusionTexture),
+        "emission" => Some(GodotMaterialProperty::EmissionTexture),
+        "orm" => Some(GodotMaterialProperty::OrmTexture),
+        _ => None,
+    }
+}
+
+/// Generate the random suffix of the ids of the ext_resource tags, such as "1_x4k2p"
 fn generate_godot_uid(length: usize) -> String {
     Alphanumeric.sample_string(&mut thread_rng(), length).to_lowercase()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn header_uid_uses_godot_digits() {
+        for _ in 0..100 {
+            let mut mat_data = String::new();
+            generate_header(&mut mat_data, GodotMaterialType::Standard);
+
+            let uid = mat_data
+                .split("uid://")
+                .nth(1)
+                .and_then(|rest| rest.split('"').next())
+                .unwrap();
+            assert!(!uid.is_empty());
+            assert!(uid.bytes().all(|c| matches!(c, b'a'..=b'y' | b'0'..=b'8')), "uid://{}", uid);
+        }
+    }
+
+    /// The transparency lines written for the alpha with the settings
+    fn transparency(alpha: Alpha, settings: &MaterialSettings) -> String {
+        let mut mat_data = String::new();
+        generate_transparency(&mut mat_data, alpha, settings);
+        mat_data
+    }
+
+    #[test]
+    fn transparency_follows_the_alpha() {
+        let settings = MaterialSettings::default();
+
+        assert_eq!(transparency(Alpha::Opaque, &settings), "");
+        assert_eq!(
+            transparency(Alpha::Cutout, &settings),
+            "\ntransparency = 2\nalpha_scissor_threshold = 0.5\ncull_mode = 2",
+        );
+        assert_eq!(transparency(Alpha::Translucent, &settings), "\ntransparency = 1\ncull_mode = 2");
+    }
+
+    #[test]
+    fn transparency_settings_take_precedence() {
+        let settings = MaterialSettings {
+            transparency: Some(Transparency::Scissor),
+            alpha_scissor_threshold: 0.25,
+            cull_mode: Some(CullMode::Back),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            transparency(Alpha::Translucent, &settings),
+            "\ntransparency = 2\nalpha_scissor_threshold = 0.25\ncull_mode = 0",
+        );
+        // Opaque materials have no transparency to pick, but keep the cull mode
+        assert_eq!(transparency(Alpha::Opaque, &settings), "\ncull_mode = 0");
+
+        let settings = MaterialSettings { transparency: Some(Transparency::Disabled), ..Default::default() };
+        assert_eq!(transparency(Alpha::Cutout, &settings), "");
+    }
+
+    /// A texture of the material, referenced by its path
+    fn mapping(index: usize, property: GodotMaterialProperty, channel: Option<TextureChannel>) -> GodotMaterialMapping {
+        GodotMaterialMapping {
+            uid: None,
+            short_uid: format!("{}_abcde", index),
+            source_file: format!("res://textures/{}.png", property.hint()),
+            property,
+            channel,
+            alpha: Alpha::Opaque,
+        }
+    }
+
+    /// The lines of the [resource] section of the material
+    fn resource_lines(material: &str) -> Vec<&str> {
+        material.split("[resource]").nth(1).unwrap().lines().filter(|line| !line.is_empty()).collect()
+    }
+
+    #[test]
+    fn orm_material_for_a_packed_texture() {
+        let mapping = vec![
+            mapping(1, GodotMaterialProperty::AlbedoTexture, None),
+            mapping(2, GodotMaterialProperty::OrmTexture, None),
+        ];
+        let material = generate_material(&mapping, &MaterialSettings::default());
+
+        assert!(material.starts_with("[gd_resource type=\"ORMMaterial3D\" format=3 uid=\"uid://"));
+        assert!(material.contains("[ext_resource type=\"Texture2D\" path=\"res://textures/orm.png\" id=\"2_abcde\"]\n"));
+        assert_eq!(resource_lines(&material), [
+            "albedo_texture = ExtResource(\"1_abcde\")",
+            "ao_enabled = true",
+            "metallic = 1.0",
+            "orm_texture = ExtResource(\"2_abcde\")",
+        ]);
+    }
+
+    #[test]
+    fn standard_material_reads_the_rest_from_the_orm_texture() {
+        let mapping = vec![
+            mapping(1, GodotMaterialProperty::OrmTexture, None),
+            mapping(2, GodotMaterialProperty::RoughnessTexture, None),
+        ];
+        assert!(get_godot_material_type(&mapping) == GodotMaterialType::Standard);
+
+        let material = generate_material(&mapping, &MaterialSettings::default());
+        assert!(material.starts_with("[gd_resource type=\"StandardMaterial3D\""));
+        assert_eq!(resource_lines(&material), [
+            "ao_enabled = true",
+            "ao_texture = ExtResource(\"1_abcde\")",
+            "ao_texture_channel = 0",
+            "metallic = 1.0",
+            "metallic_texture = ExtResource(\"1_abcde\")",
+            "metallic_texture_channel = 2",
+            "roughness_texture = ExtResource(\"2_abcde\")",
+        ]);
+    }
+
+    #[test]
+    fn texture_channels_of_the_texture_and_settings() {
+        let mut settings = MaterialSettings::default();
+        settings.texture_channels.insert(String::from("metallic"), TextureChannel::Blue);
+        settings.texture_channels.insert(String::from("roughness"), TextureChannel::Blue);
+
+        let mapping = vec![
+            mapping(1, GodotMaterialProperty::RoughnessTexture, Some(TextureChannel::Green)),
+            mapping(2, GodotMaterialProperty::MetallicTexture, None),
+            mapping(3, GodotMaterialProperty::AmbientOcclusionTexture, None),
+        ];
+        let material = generate_material(&mapping, &settings);
+
+        // The channel of the texture wins over the settings, and without either Godot reads red
+        assert_eq!(resource_lines(&material), [
+            "roughness_texture = ExtResource(\"1_abcde\")",
+            "roughness_texture_channel = 1",
+            "metallic = 1.0",
+            "metallic_texture = ExtResource(\"2_abcde\")",
+            "metallic_texture_channel = 2",
+            "ao_enabled = true",
+            "ao_texture = ExtResource(\"3_abcde\")",
+        ]);
+    }
+
+    #[test]
+    fn emission_uses_the_settings() {
+        let settings = MaterialSettings {
+            emission_color: Color { r: 1.0, g: 0.5, b: 0.0 },
+            emission_energy: 4.0,
+            ..Default::default()
+        };
+        let mut emission = mapping(1, GodotMaterialProperty::EmissionTexture, None);
+        emission.uid = Some(String::from("b3kx2q1n0r7m"));
+        let material = generate_material(&vec![emission], &settings);
+
+        assert!(material.contains("path=\"res://textures/emission.png\" uid=\"uid://b3kx2q1n0r7m\" id=\"1_abcde\"]"));
+        assert_eq!(resource_lines(&material), [
+            "emission_enabled = true",
+            "emission = Color(1, 0.5, 0, 1)",
+            "emission_energy_multiplier = 4.0",
+            "emission_texture = ExtResource(\"1_abcde\")",
+        ]);
+    }
+}
@@ -0,0 +1,117 @@
+use std::fmt;
+use std::str::FromStr;
+
+/// Normal map convention
+/// Godot expects OpenGL-style normal maps (Y+), while some sources ship DirectX-style (Y-)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NormalConvention {
+    OpenGl,
+    DirectX,
+}
+
+impl fmt::Display for NormalConvention {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NormalConvention::OpenGl => write!(f, "OpenGL (Y+)"),
+            NormalConvention::DirectX => write!(f, "DirectX (Y-)"),
+        }
+    }
+}
+
+impl FromStr for NormalConvention {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name.to_lowercase().as_str() {
+            "gl" | "opengl" => Ok(NormalConvention::OpenGl),
+            "dx" | "directx" => Ok(NormalConvention::DirectX),
+            _ => Err(String::from("expected gl or dx")),
+        }
+    }
+}
+
+/// Vendor preset
+/// The naming conventions of common texture sources: which words they use for each
+/// channel, how they tag resolutions (and other noise in the filenames), and which
+/// normal map convention their normal maps follow
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Preset {
+    AmbientCg,
+    PolyHaven,
+    Poliigon,
+    Megascans,
+    Substance,
+}
+
+impl Preset {
+    /// The names used to select the presets
+    pub const NAMES: [&'static str; 5] = ["ambientcg", "polyhaven", "poliigon", "megascans", "substance"];
+
+    /// The channel synonyms of the vendor which the built-in synonyms lack, and which
+    /// take precedence over them. The names of ambientCG, Poly Haven and Megascans
+    /// (e.g. "Rock042_4K-PNG_Color" or "rock_wall_diff_4k") only use built-in synonyms.
+    pub(crate) fn synonyms(&self) -> &'static [(&'static str, &'static [&'static str])] {
+        match self {
+            Preset::AmbientCg | Preset::PolyHaven | Preset::Megascans => &[],
+            // The variation and bit depth belong to the hint, as in "Wood_COL_VAR1_3K" or "Wood_NRM16_3K"
+            Preset::Poliigon => &[
+                ("albedo", &["colvar1", "colvar2", "colvar3", "colvar4"]),
+                ("normal", &["nrm16"]),
+                ("height", &["disp16", "bump16"]),
+            ],
+            Preset::Substance => &[
+                ("normal", &["normalopengl", "normaldirectx"]),
+                ("ao", &["mixedao"]),
+            ],
+        }
+    }
+
+    /// Matches the words the vendor adds to filenames besides the channel and
+    /// resolution, such as the file type in ambientCG's "Rock042_4K-PNG_Color",
+    /// the variation in Poliigon's "Wood_COL_VAR1_3K", or the level of detail
+    /// in Megascans' "Rock_4K_Normal_LOD0"
+    pub(crate) fn tag_expression(&self) -> Option<&'static str> {
+        match self {
+            Preset::AmbientCg => Some(r"^(?i:png|jpg|exr)$"),
+            Preset::Poliigon => Some(r"^(?i:var\d+|metalness|specular)$"),
+            Preset::Megascans => Some(r"^(?i:lod\d+)$"),
+            _ => None,
+        }
+    }
+
+    /// Matches the suffix the vendor puts after the channel, which must be ignored
+    /// when detecting the channel. Poliigon ends every filename with the workflow,
+    /// like "Wood_COL_3K_METALNESS", which would otherwise be detected as metallic.
+    pub(crate) fn suffix_expression(&self) -> Option<&'static str> {
+        match self {
+            Preset::Poliigon => Some(r"(?i)[_\- ](metalness|specular)$"),
+            _ => None,
+        }
+    }
+
+    /// The convention the vendor's normal maps follow, unless the filename says otherwise
+    pub fn normal_convention(&self) -> NormalConvention {
+        match self {
+            Preset::AmbientCg => NormalConvention::OpenGl,
+            Preset::PolyHaven => NormalConvention::OpenGl,
+            Preset::Poliigon => NormalConvention::OpenGl,
+            Preset::Megascans => NormalConvention::OpenGl,
+            Preset::Substance => NormalConvention::DirectX,
+        }
+    }
+}
+
+impl FromStr for Preset {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name.to_lowercase().as_str() {
+            "ambientcg" => Ok(Preset::AmbientCg),
+            "polyhaven" => Ok(Preset::PolyHaven),
+            "poliigon" => Ok(Preset::Poliigon),
+            "megascans" => Ok(Preset::Megascans),
+            "substance" => Ok(Preset::Substance),
+            _ => Err(format!("expected one of: {}", Preset::NAMES.join(", "))),
+        }
+    }
+}
@@ -0,0 +1,91 @@
+use std::ffi::OsStr;
+use std::fs;
+use std::path::{Path, PathBuf};
+use crate::MaterialError;
+
+/// The file which marks the root directory of a Godot project
+const PROJECT_FILE: &str = "project.godot";
+
+/// The ``res://`` path of a file, relative to the Godot project it's in
+///
+/// The project is found by walking up from the directory of the file, until a directory
+/// with a ``project.godot`` file turns up. Neither the file nor its directory needs to
+/// exist yet, e.g. a --destination in preview mode.
+pub(crate) fn resource_path(file: &Path) -> Result<String, MaterialError> {
+    let no_project = || MaterialError::NoProject { path: file.to_path_buf() };
+
+    let name = file.file_name().ok_or_else(no_project)?;
+    let parent = match file.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+
+    // Canonical paths resolve "..", and symlinks into the project. Only the closest existing
+    // directory can be canonicalized, so the missing ones below it are added back afterward.
+    let mut existing: &Path = parent;
+    let mut missing: Vec<&OsStr> = Vec::new();
+    while !existing.is_dir() {
+        missing.push(existing.file_name().ok_or_else(no_project)?);
+        existing = match existing.parent() {
+            Some(ancestor) if !ancestor.as_os_str().is_empty() => ancestor,
+            _ => Path::new("."),
+        };
+    }
+
+    let mut directory = fs::canonicalize(existing).map_err(|_| no_project())?;
+    directory.extend(missing.iter().rev());
+    let project = find_project_dir(&directory).ok_or_else(no_project)?;
+
+    let relative = directory
+        .strip_prefix(&project)
+        .expect("The project is one of the ancestors of the directory")
+        .join(name);
+
+    // Godot uses forward slashes, on Windows as well
+    let components: Vec<String> = relative
+        .components()
+        .map(|component| component.as_os_str().to_string_lossy().into_owned())
+        .collect();
+
+    Ok(format!("res://{}", components.join("/")))
+}
+
+/// The closest directory (the ``directory`` itself, or one above it) with a ``project.godot`` file
+fn find_project_dir(directory: &Path) -> Option<PathBuf> {
+    directory
+        .ancestors()
+        .find(|ancestor| ancestor.join(PROJECT_FILE).is_file())
+        .map(Path::to_path_buf)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::process;
+    use super::*;
+
+    #[test]
+    fn resource_path_in_project() {
+        let root = std::env::temp_dir().join(format!("gim-project-{}", process::id()));
+        let textures = root.join("assets").join("textures");
+        fs::create_dir_all(&textures).unwrap();
+        fs::write(root.join(PROJECT_FILE), "").unwrap();
+
+        let path = |file: &Path| resource_path(file).map_err(|err| err.to_string());
+        let results = [
+            path(&textures.join("rock.png")),
+            path(&textures.join("new").join("deeper").join("rock.png")),
+            path(&textures.join("..").join("other").join("rock.png")),
+            path(&root.join("rock.png")),
+        ];
+        let outside = resource_path(&root.parent().unwrap().join("rock.png"));
+        fs::remove_dir_all(&root).unwrap();
+
+        assert_eq!(results, [
+            Ok(String::from("res://assets/textures/rock.png")),
+            Ok(String::from("res://assets/textures/new/deeper/rock.png")),
+            Ok(String::from("res://assets/other/rock.png")),
+            Ok(String::from("res://rock.png")),
+        ]);
+        assert!(matches!(outside, Err(MaterialError::NoProject { .. })));
+    }
+}
@@ -0,0 +1,197 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+
+/// Color
+/// An RGB color with components in the 0.0-1.0 range, as Godot stores them
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Color {
+    pub r: f32,
+    pub g: f32,
+    pub b: f32,
+}
+
+impl Color {
+    pub const WHITE: Color = Color { r: 1.0, g: 1.0, b: 1.0 };
+}
+
+impl FromStr for Color {
+    type Err = String;
+
+    /// Parse a hex color, such as "#ff8800" or "ff8800"
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let hex = value.trim().trim_start_matches('#');
+        let invalid = || format!("\"{}\" is not a hex color, such as \"#ff8800\"", value);
+
+        if hex.len() != 6 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(invalid());
+        }
+
+        let component = |range: std::ops::Range<usize>| {
+            u8::from_str_radix(&hex[range], 16).map(|c| c as f32 / 255.0).map_err(|_| invalid())
+        };
+
+        Ok(Color { r: component(0..2)?, g: component(2..4)?, b: component(4..6)? })
+    }
+}
+
+impl fmt::Display for Color {
+    /// Format the color the way it's written in a Godot resource file
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Color({}, {}, {}, 1)", self.r, self.g, self.b)
+    }
+}
+
+/// Texture channel
+/// The channel of a texture a material property reads from, for instance when
+/// several properties share a packed texture
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TextureChannel {
+    Red,
+    Green,
+    Blue,
+    Alpha,
+    Grayscale,
+}
+
+impl TextureChannel {
+    /// The value of Godot's ``BaseMaterial3D.TextureChannel`` enum
+    pub fn godot_value(&self) -> u8 {
+        match self {
+            TextureChannel::Red => 0,
+            TextureChannel::Green => 1,
+            TextureChannel::Blue => 2,
+            TextureChannel::Alpha => 3,
+            TextureChannel::Grayscale => 4,
+        }
+    }
+}
+
+impl FromStr for TextureChannel {
+    type Err = String;
+
+    /// Parse a channel, such as "g", "green" or "gray"
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.trim().to_lowercase().as_str() {
+            "r" | "red" => Ok(TextureChannel::Red),
+            "g" | "green" => Ok(TextureChannel::Green),
+            "b" | "blue" => Ok(TextureChannel::Blue),
+            "a" | "alpha" => Ok(TextureChannel::Alpha),
+            "gray" | "grey" | "grayscale" => Ok(TextureChannel::Grayscale),
+            _ => Err(format!("\"{}\" is not a channel, expected r, g, b, a or gray", value)),
+        }
+    }
+}
+
+/// Transparency
+/// How Godot renders a material whose albedo has transparent pixels
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Transparency {
+    Disabled,
+    /// Alpha blending, for translucent surfaces such as glass
+    Blend,
+    /// Pixels below the threshold are discarded, for cutouts such as foliage and fences
+    Scissor,
+    /// Dithered cutout, which looks softer at a distance
+    Hash,
+    /// Alpha blending, with a depth pre-pass which sorts the surface correctly
+    DepthPrePass,
+}
+
+impl Transparency {
+    pub const NAMES: [&'static str; 5] = ["disabled", "blend", "scissor", "hash", "depth-prepass"];
+
+    /// The value of Godot's ``BaseMaterial3D.Transparency`` enum
+    pub fn godot_value(&self) -> u8 {
+        match self {
+            Transparency::Disabled => 0,
+            Transparency::Blend => 1,
+            Transparency::Scissor => 2,
+            Transparency::Hash => 3,
+            Transparency::DepthPrePass => 4,
+        }
+    }
+}
+
+impl FromStr for Transparency {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.trim().to_lowercase().as_str() {
+            "disabled" => Ok(Transparency::Disabled),
+            "blend" => Ok(Transparency::Blend),
+            "scissor" => Ok(Transparency::Scissor),
+            "hash" => Ok(Transparency::Hash),
+            "depth-prepass" => Ok(Transparency::DepthPrePass),
+            _ => Err(format!("expected one of: {}", Transparency::NAMES.join(", "))),
+        }
+    }
+}
+
+/// Cull mode
+/// Which faces of the material Godot skips when rendering
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CullMode {
+    Back,
+    Front,
+    /// Both sides are rendered
+    Disabled,
+}
+
+impl CullMode {
+    pub const NAMES: [&'static str; 3] = ["back", "front", "disabled"];
+
+    /// The value of Godot's ``BaseMaterial3D.CullMode`` enum
+    pub fn godot_value(&self) -> u8 {
+        match self {
+            CullMode::Back => 0,
+            CullMode::Front => 1,
+            CullMode::Disabled => 2,
+        }
+    }
+}
+
+impl FromStr for CullMode {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.trim().to_lowercase().as_str() {
+            "back" => Ok(CullMode::Back),
+            "front" => Ok(CullMode::Front),
+            "disabled" => Ok(CullMode::Disabled),
+            _ => Err(format!("expected one of: {}", CullMode::NAMES.join(", "))),
+        }
+    }
+}
+
+/// Material settings
+/// Values written to the material which can't be derived from the textures
+#[derive(Debug, Clone)]
+pub struct MaterialSettings {
+    /// The color the emission texture is multiplied with
+    pub emission_color: Color,
+    /// The strength of the emission
+    pub emission_energy: f32,
+    /// The channel each property (such as "roughness") reads from its texture
+    pub texture_channels: HashMap<String, TextureChannel>,
+    /// The transparency of materials whose albedo has transparent pixels, which is
+    /// picked from the kind of transparency when not given
+    pub transparency: Option<Transparency>,
+    /// The alpha below which pixels are discarded, in scissor mode
+    pub alpha_scissor_threshold: f32,
+    /// The cull mode, which defaults to rendering both sides of transparent materials
+    pub cull_mode: Option<CullMode>,
+}
+
+impl Default for MaterialSettings {
+    fn default() -> Self {
+        MaterialSettings {
+            emission_color: Color::WHITE,
+            emission_energy: 1.0,
+            texture_channels: HashMap::new(),
+            transparency: None,
+            alpha_scissor_threshold: 0.5,
+            cull_mode: None,
+        }
+    }
+}
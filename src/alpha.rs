@@ -0,0 +1,153 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use image::{DynamicImage, GenericImageView, RgbaImage};
+use image::imageops::{self, FilterType};
+use material::Alpha;
+use crate::{channel_of, material_key, Derived, MaterialKey, Options, SourceFile};
+
+/// The share of the non-opaque pixels which may be partially transparent, for the
+/// texture to still count as a cutout. Cutouts have soft edges, but no more.
+const CUTOUT_SOFT_EDGE_RATIO: f32 = 0.1;
+
+/// Merge opacity maps into the alpha channel of the albedo map of their material
+///
+/// The maps of a material (see ``material_key``), such as "Leaf_Color" and "Leaf_Opacity",
+/// are merged into the albedo file, which keeps its name. Opacity maps without an albedo map,
+/// and albedo maps which are derived from other maps already, are left as they are.
+pub fn merge_opacity(files: Vec<SourceFile>, options: &Options) -> Vec<SourceFile> {
+    let mut materials: HashMap<MaterialKey, (Option<usize>, Option<usize>)> = HashMap::new();
+
+    for (index, file) in files.iter().enumerate() {
+        let (Some((channel, _)), Some(key)) = (channel_of(file, options), material_key(&file.path, options)) else {
+            continue;
+        };
+        let slots = materials.entry(key).or_default();
+
+        match channel.as_str() {
+            "albedo" if file.derived.is_none() => { slots.0.get_or_insert(index); },
+            "opacity" if file.derived.is_none() => { slots.1.get_or_insert(index); },
+            _ => {},
+        }
+    }
+
+    let merges: HashMap<usize, usize> = materials
+        .into_values()
+        .filter_map(|slots| match slots {
+            (Some(albedo), Some(opacity)) => Some((albedo, opacity)),
+            _ => None,
+        })
+        .collect();
+
+    let opacity_paths: HashMap<usize, PathBuf> = merges
+        .values()
+        .map(|&opacity| (opacity, files[opacity].path.clone()))
+        .collect();
+
+    files
+        .into_iter()
+        .enumerate()
+        .filter(|(index, _)| !opacity_paths.contains_key(index))
+        .map(|(index, mut file)| {
+            if let Some(opacity) = merges.get(&index) {
+                file.derived = Some(Derived::AlbedoWithOpacity {
+                    albedo: file.path.clone(),
+                    opacity: opacity_paths[opacity].clone(),
+                });
+            }
+            file
+        })
+        .collect()
+}
+
+/// Put the brightness of the opacity map into the alpha channel of the albedo map
+/// The opacity map is resized to the size of the albedo map, if they differ.
+pub fn with_opacity(albedo: &DynamicImage, opacity: &DynamicImage) -> DynamicImage {
+    let (width, height) = albedo.dimensions();
+    let mut opacity = opacity.to_luma8();
+
+    if opacity.dimensions() != (width, height) {
+        opacity = imageops::resize(&opacity, width, height, FilterType::Triangle);
+    }
+
+    let mut merged: RgbaImage = albedo.to_rgba8();
+    for (pixel, alpha) in merged.pixels_mut().zip(opacity.pixels()) {
+        pixel[3] = alpha[0];
+    }
+
+    DynamicImage::ImageRgba8(merged)
+}
+
+/// Find out what kind of transparency the alpha channel of the image holds
+///
+/// Alpha below 5% counts as transparent, and above 95% as opaque. If more than a tenth
+/// of the pixels which aren't opaque lie in between, the image is translucent, otherwise
+/// it's a cutout.
+pub fn coverage(img: &DynamicImage) -> Alpha {
+    if !img.color().has_alpha() {
+        return Alpha::Opaque;
+    }
+
+    let (mut transparent, mut partial) = (0usize, 0usize);
+    for alpha in img.to_rgba8().pixels().map(|pixel| pixel[3]) {
+        match alpha {
+            0..=12 => transparent += 1,
+            243..=255 => {},
+            _ => partial += 1,
+        }
+    }
+
+    match transparent + partial {
+        0 => Alpha::Opaque,
+        non_opaque if (partial as f32) / (non_opaque as f32) > CUTOUT_SOFT_EDGE_RATIO => Alpha::Translucent,
+        _ => Alpha::Cutout,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{GrayImage, Luma, Rgba};
+    use super::*;
+
+    /// A 10x10 image with the given number of transparent and partially transparent pixels
+    fn with_alpha(transparent: usize, partial: usize) -> DynamicImage {
+        let mut img = RgbaImage::from_pixel(10, 10, Rgba([50, 100, 150, 255]));
+        for (index, pixel) in img.pixels_mut().enumerate() {
+            if index < transparent {
+                pixel[3] = 0;
+            } else if index < transparent + partial {
+                pixel[3] = 128;
+            }
+        }
+        DynamicImage::ImageRgba8(img)
+    }
+
+    #[test]
+    fn coverage_cutout_or_translucent() {
+        assert_eq!(coverage(&with_alpha(0, 0)), Alpha::Opaque);
+        assert_eq!(coverage(&DynamicImage::ImageRgb8(with_alpha(50, 50).to_rgb8())), Alpha::Opaque);
+        assert_eq!(coverage(&with_alpha(50, 0)), Alpha::Cutout);
+
+        // Up to a tenth of the non-opaque pixels may be soft edges
+        assert_eq!(coverage(&with_alpha(45, 5)), Alpha::Cutout);
+        assert_eq!(coverage(&with_alpha(44, 6)), Alpha::Translucent);
+        assert_eq!(coverage(&with_alpha(0, 1)), Alpha::Translucent);
+    }
+
+    #[test]
+    fn coverage_thresholds() {
+        let mut img = RgbaImage::from_pixel(2, 2, Rgba([0, 0, 0, 255]));
+        img.put_pixel(0, 0, Rgba([0, 0, 0, 12]));
+        img.put_pixel(1, 0, Rgba([0, 0, 0, 243]));
+        assert_eq!(coverage(&DynamicImage::ImageRgba8(img)), Alpha::Cutout);
+    }
+
+    #[test]
+    fn with_opacity_sets_alpha() {
+        let albedo = DynamicImage::ImageRgb8(image::RgbImage::from_pixel(4, 4, image::Rgb([50, 100, 150])));
+        let opacity = DynamicImage::ImageLuma8(GrayImage::from_pixel(2, 2, Luma([77])));
+        let merged = with_opacity(&albedo, &opacity);
+
+        assert_eq!(merged.dimensions(), (4, 4));
+        assert!(merged.to_rgba8().pixels().all(|pixel| pixel.0 == [50, 100, 150, 77]));
+    }
+}
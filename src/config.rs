@@ -0,0 +1,358 @@
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use clap::ArgMatches;
+use clap::parser::ValueSource;
+use serde::Deserialize;
+use material::{HintDictionary, TextureChannel};
+use crate::{ConversionError, Options};
+use crate::format::{OutputFormat, PngCompression};
+use crate::depth::{BitDepth, DepthSettings};
+use crate::normal::NormalFilter;
+use crate::pack::PackMode;
+use crate::resize::{PotMode, ResizeFilter, ResizeSettings};
+use crate::pattern::{self, MatchMode};
+
+/// The config file discovered in the working directory, when ``--config`` isn't given
+pub const DEFAULT_CONFIG_FILE: &str = "gim.toml";
+
+/// Project config
+/// Mirrors the command line options, which serve as defaults when the option isn't
+/// given on the command line, along with the per-pattern rules
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    search_pattern: Option<String>,
+    inputs: Option<Vec<PathBuf>>,
+    allow_overwrites: Option<bool>,
+    destination: Option<String>,
+    delete_sources: Option<bool>,
+    preview: Option<bool>,
+    material: Option<bool>,
+    group_pattern: Option<String>,
+    material_name: Option<String>,
+    material_dir: Option<PathBuf>,
+    import_timeout: Option<u64>,
+    offline: Option<bool>,
+    write_import: Option<bool>,
+    format: Option<OutputFormat>,
+    jpeg_quality: Option<u8>,
+    png_compression: Option<PngCompression>,
+    recursive: Option<bool>,
+    max_depth: Option<usize>,
+    follow_symlinks: Option<bool>,
+    match_path: Option<bool>,
+    #[serde(rename = "match")]
+    match_mode: Option<MatchMode>,
+    patterns: Option<Vec<String>>,
+    exclude: Option<Vec<String>>,
+    jobs: Option<usize>,
+    rename: Option<String>,
+    max_size: Option<u32>,
+    scale: Option<f32>,
+    pot: Option<PotMode>,
+    filter: Option<ResizeFilter>,
+    bit_depth: Option<BitDepth>,
+    strip_alpha: Option<bool>,
+    dither: Option<bool>,
+    spec_gloss: Option<bool>,
+    merge_opacity: Option<bool>,
+    normal_from_height: Option<bool>,
+    normal_filter: Option<NormalFilter>,
+    normal_strength: Option<f32>,
+    normal_wrap: Option<bool>,
+    pack: Option<PackMode>,
+    preset: Option<String>,
+    normal_convention: Option<String>,
+    emission_color: Option<String>,
+    emission_energy: Option<f32>,
+    transparency: Option<String>,
+    alpha_threshold: Option<f32>,
+    cull_mode: Option<String>,
+    /// Channel synonyms, such as ``paint = "albedo"``
+    #[serde(default)]
+    hints: BTreeMap<String, String>,
+    /// The channel of its texture a property reads, such as ``roughness = "g"``
+    #[serde(default)]
+    texture_channels: BTreeMap<String, String>,
+    /// Settings per channel, such as ``[channels.normal]``
+    #[serde(default)]
+    channels: BTreeMap<String, ChannelSettings>,
+    #[serde(default)]
+    rules: Vec<Rule>,
+}
+
+/// Conversion rule
+/// Settings applied to the files matching ``pattern`` (a glob, such as "*_normal.*").
+/// When several rules match a file, the first one wins.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct Rule {
+    pub pattern: String,
+    pub format: Option<OutputFormat>,
+    pub destination: Option<String>,
+    /// The material property the files map to, such as "albedo" or "normal" (stored in lowercase)
+    pub hint: Option<String>,
+    /// The channel of the files the property reads, such as "g" or "gray"
+    pub channel: Option<String>,
+    /// Rename template, see ``--rename``
+    pub rename: Option<String>,
+    pub max_size: Option<u32>,
+    pub scale: Option<f32>,
+    pub pot: Option<PotMode>,
+    pub filter: Option<ResizeFilter>,
+    pub bit_depth: Option<BitDepth>,
+    pub strip_alpha: Option<bool>,
+    pub dither: Option<bool>,
+}
+
+impl Rule {
+    /// The resize settings given by the rule
+    pub fn resize_settings(&self) -> ResizeSettings {
+        ResizeSettings { max_size: self.max_size, scale: self.scale, pot: self.pot, filter: self.filter }
+    }
+
+    /// The depth settings given by the rule
+    pub fn depth_settings(&self) -> DepthSettings {
+        DepthSettings { bit_depth: self.bit_depth, strip_alpha: self.strip_alpha, dither: self.dither }
+    }
+}
+
+/// Channel settings
+/// Settings applied to the files of a channel, such as all normal maps, which
+/// override the command line options. Rules override these in turn.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct ChannelSettings {
+    pub max_size: Option<u32>,
+    pub scale: Option<f32>,
+    pub pot: Option<PotMode>,
+    pub filter: Option<ResizeFilter>,
+    pub bit_depth: Option<BitDepth>,
+    pub strip_alpha: Option<bool>,
+    pub dither: Option<bool>,
+}
+
+impl ChannelSettings {
+    /// The resize settings given for the channel
+    pub fn resize_settings(&self) -> ResizeSettings {
+        ResizeSettings { max_size: self.max_size, scale: self.scale, pot: self.pot, filter: self.filter }
+    }
+
+    /// The depth settings given for the channel
+    pub fn depth_settings(&self) -> DepthSettings {
+        DepthSettings { bit_depth: self.bit_depth, strip_alpha: self.strip_alpha, dither: self.dither }
+    }
+}
+
+/// Locate and read the config file, and apply it to the ``options``
+///
+/// The file given with ``--config`` is required to exist, while the ``gim.toml``
+/// in the working directory is only used if it's there.
+/// Options given on the command line always take precedence over the config file.
+pub fn load(options: &mut Options, matches: &ArgMatches) -> Result<(), ConversionError> {
+    let path: PathBuf = match &options.config {
+        Some(path) => path.clone(),
+        None => {
+            let default_path = env::current_dir()
+                .map_err(|error| ConversionError::Io { path: PathBuf::from("."), error })?
+                .join(DEFAULT_CONFIG_FILE);
+
+            if !default_path.is_file() {
+                return Ok(());
+            }
+
+            default_path
+        },
+    };
+
+    let data = fs::read_to_string(&path)
+        .map_err(|error| ConversionError::Io { path: path.clone(), error })?;
+
+    let mut config: Config = toml::from_str(&data)
+        .map_err(|err| ConversionError::Config { path: path.clone(), reason: err.to_string() })?;
+
+    // Rule patterns, hints and channels are used when the files are collected, so they're checked up front
+    for rule in &mut config.rules {
+        pattern::generate_regex(&rule.pattern, MatchMode::Glob)
+            .map_err(|reason| ConversionError::Config { path: path.clone(), reason })?;
+
+        if let Some(hint) = &mut rule.hint {
+            *hint = HintDictionary::channel(hint)
+                .map_err(|reason| ConversionError::Config { path: path.clone(), reason })?
+                .to_owned();
+        }
+
+        if let Some(channel) = &rule.channel {
+            channel.parse::<TextureChannel>()
+                .map_err(|reason| ConversionError::Config { path: path.clone(), reason })?;
+        }
+
+        rule.resize_settings().validate()
+            .map_err(|reason| ConversionError::Config { path: path.clone(), reason: format!("rule \"{}\": {}", rule.pattern, reason) })?;
+    }
+
+    for (channel, settings) in &config.channels {
+        settings.resize_settings().validate()
+            .map_err(|reason| ConversionError::Config { path: path.clone(), reason: format!("[channels.{}] {}", channel, reason) })?;
+    }
+
+    // Relative inputs in the config file are relative to the config file itself
+    let config_dir: PathBuf = path.parent().map(Path::to_path_buf).unwrap_or_default();
+    apply(config, &config_dir, options, matches);
+
+    // The ranges the command line enforces don't apply to the values from the config file
+    if !(1..=100).contains(&options.jpeg_quality) {
+        return Err(ConversionError::Config {
+            path,
+            reason: format!("jpeg_quality must be between 1 and 100, not {}", options.jpeg_quality),
+        });
+    }
+
+    Ok(())
+}
+
+/// Copy the values from the config file onto the options, except those
+/// which were given explicitly on the command line
+fn apply(config: Config, config_dir: &Path, options: &mut Options, matches: &ArgMatches) {
+    let from_command_line = |id: &str| {
+        matches.value_source(id) == Some(ValueSource::CommandLine)
+    };
+
+    macro_rules! apply {
+        ($field:ident) => {
+            if let (false, Some(value)) = (from_command_line(stringify!($field)), config.$field) {
+                options.$field = value;
+            }
+        };
+    }
+
+    // When the config file sets the search pattern, every positional argument is an input,
+    // so "gim textures" searches the "textures" directory. Use --pattern for additional patterns.
+    let has_positional_input = config.search_pattern.is_some() && from_command_line("search_pattern");
+    if config.search_pattern.is_some() {
+        if let (true, Some(input)) = (has_positional_input, options.search_pattern.take()) {
+            options.inputs.insert(0, PathBuf::from(input));
+        }
+        options.search_pattern = config.search_pattern;
+    }
+
+    // Inputs can come from both the positional arguments and --input
+    if !has_positional_input && !from_command_line("inputs") && !from_command_line("input") {
+        if let Some(inputs) = config.inputs {
+            options.inputs = inputs.into_iter().map(|input| config_dir.join(input)).collect();
+        }
+    }
+
+    if !from_command_line("destination") && config.destination.is_some() {
+        options.destination = config.destination;
+    }
+
+    if !from_command_line("max_depth") && config.max_depth.is_some() {
+        options.max_depth = config.max_depth;
+    }
+
+    if !from_command_line("group_pattern") && config.group_pattern.is_some() {
+        options.group_pattern = config.group_pattern;
+    }
+
+    // Like the inputs, the material directory is relative to the config file
+    if let (false, Some(material_dir)) = (from_command_line("material_dir"), config.material_dir) {
+        options.material_dir = Some(config_dir.join(material_dir));
+    }
+
+    if !from_command_line("rename") && config.rename.is_some() {
+        options.rename = config.rename;
+    }
+
+    if !from_command_line("max_size") && config.max_size.is_some() {
+        options.max_size = config.max_size;
+    }
+
+    if !from_command_line("scale") && config.scale.is_some() {
+        options.scale = config.scale;
+    }
+
+    if !from_command_line("pot") && config.pot.is_some() {
+        options.pot = config.pot;
+    }
+
+    if !from_command_line("filter") && config.filter.is_some() {
+        options.filter = config.filter;
+    }
+
+    if !from_command_line("bit_depth") && config.bit_depth.is_some() {
+        options.bit_depth = config.bit_depth;
+    }
+
+    if !from_command_line("pack") && config.pack.is_some() {
+        options.pack = config.pack;
+    }
+
+    if !from_command_line("preset") && config.preset.is_some() {
+        options.preset = config.preset;
+    }
+
+    if !from_command_line("transparency") && config.transparency.is_some() {
+        options.transparency = config.transparency;
+    }
+
+    if !from_command_line("cull_mode") && config.cull_mode.is_some() {
+        options.cull_mode = config.cull_mode;
+    }
+
+    if !from_command_line("normal_convention") && config.normal_convention.is_some() {
+        options.normal_convention = config.normal_convention;
+    }
+
+    apply!(allow_overwrites);
+    apply!(delete_sources);
+    apply!(preview);
+    apply!(material);
+    apply!(format);
+    apply!(jpeg_quality);
+    apply!(png_compression);
+    apply!(recursive);
+    apply!(follow_symlinks);
+    apply!(match_path);
+    apply!(match_mode);
+    apply!(patterns);
+    apply!(exclude);
+    apply!(jobs);
+    apply!(spec_gloss);
+    apply!(merge_opacity);
+    apply!(normal_from_height);
+    apply!(normal_filter);
+    apply!(normal_strength);
+    apply!(normal_wrap);
+    apply!(strip_alpha);
+    apply!(dither);
+    apply!(emission_color);
+    apply!(emission_energy);
+    apply!(material_name);
+    apply!(import_timeout);
+    apply!(offline);
+    apply!(write_import);
+    apply!(alpha_threshold);
+
+    // Synonyms from the config file are added before those from the command line,
+    // so the command line wins when both define the same synonym
+    let mut hints: Vec<String> = config.hints
+        .into_iter()
+        .map(|(synonym, channel)| format!("{}={}", synonym, channel))
+        .collect();
+    hints.append(&mut options.hints);
+    options.hints = hints;
+
+    // Same goes for the texture channels
+    let mut texture_channels: Vec<String> = config.texture_channels
+        .into_iter()
+        .map(|(property, channel)| format!("{}={}", property, channel))
+        .collect();
+    texture_channels.append(&mut options.texture_channels);
+    options.texture_channels = texture_channels;
+
+    options.channel_settings = config.channels;
+    options.rules = config.rules;
+}
@@ -0,0 +1,188 @@
+use clap::ValueEnum;
+use serde::Deserialize;
+use image::{DynamicImage, ImageBuffer, Rgba, RgbaImage};
+
+/// 4x4 Bayer matrix, used for ordered dithering
+const BAYER: [[u8; 4]; 4] = [
+    [0, 8, 2, 10],
+    [12, 4, 14, 6],
+    [3, 11, 1, 9],
+    [15, 7, 13, 5],
+];
+
+/// Bit depth
+/// The number of bits per channel of the output images
+#[derive(ValueEnum, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(try_from = "u8")]
+pub enum BitDepth {
+    #[value(name = "8")]
+    Eight,
+    #[value(name = "16")]
+    Sixteen,
+}
+
+impl TryFrom<u8> for BitDepth {
+    type Error = String;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            8 => Ok(BitDepth::Eight),
+            16 => Ok(BitDepth::Sixteen),
+            _ => Err(format!("bit depth must be 8 or 16, not {}", value)),
+        }
+    }
+}
+
+/// Depth settings
+/// Controls the bit depth and channels of the output images. Settings which aren't
+/// given keep those of the source.
+#[derive(Default, Clone, Copy, Debug)]
+pub struct DepthSettings {
+    pub bit_depth: Option<BitDepth>,
+    /// Drop the alpha channel, if every pixel is opaque
+    pub strip_alpha: Option<bool>,
+    /// Use ordered dithering when reducing 16-bit (or floating point) images to 8-bit
+    pub dither: Option<bool>,
+}
+
+impl DepthSettings {
+    /// Override these settings with those given in ``other``
+    pub fn merge(self, other: DepthSettings) -> DepthSettings {
+        DepthSettings {
+            bit_depth: other.bit_depth.or(self.bit_depth),
+            strip_alpha: other.strip_alpha.or(self.strip_alpha),
+            dither: other.dither.or(self.dither),
+        }
+    }
+}
+
+/// Convert the image to the bit depth, and strip its alpha channel, as requested
+///
+/// Grayscale images stay grayscale. Note that the output format may limit the
+/// bit depth further, e.g. JPEG only stores 8-bit.
+pub fn convert(img: DynamicImage, settings: &DepthSettings) -> DynamicImage {
+    let color = img.color();
+    let is_high_precision = color.bytes_per_pixel() / color.channel_count() > 1;
+    let strip_alpha = settings.strip_alpha.unwrap_or(false) && color.has_alpha() && is_opaque(&img);
+
+    let bit_depth = match (settings.bit_depth, strip_alpha) {
+        (Some(bit_depth), _) => bit_depth,
+        (None, true) if is_high_precision => BitDepth::Sixteen,
+        (None, true) => BitDepth::Eight,
+        (None, false) => return img,
+    };
+
+    let is_gray = !color.has_color();
+    let has_alpha = color.has_alpha() && !strip_alpha;
+
+    match bit_depth {
+        BitDepth::Eight => {
+            // Dithering spreads the rounding error, so gradients don't turn into bands
+            let img = match is_high_precision && settings.dither.unwrap_or(false) {
+                true => DynamicImage::ImageRgba8(dither(&img)),
+                false => img,
+            };
+
+            match (is_gray, has_alpha) {
+                (true, false) => DynamicImage::ImageLuma8(img.to_luma8()),
+                (true, true) => DynamicImage::ImageLumaA8(img.to_luma_alpha8()),
+                (false, false) => DynamicImage::ImageRgb8(img.to_rgb8()),
+                (false, true) => DynamicImage::ImageRgba8(img.to_rgba8()),
+            }
+        },
+        BitDepth::Sixteen => match (is_gray, has_alpha) {
+            (true, false) => DynamicImage::ImageLuma16(img.to_luma16()),
+            (true, true) => DynamicImage::ImageLumaA16(img.to_luma_alpha16()),
+            (false, false) => DynamicImage::ImageRgb16(img.to_rgb16()),
+            (false, true) => DynamicImage::ImageRgba16(img.to_rgba16()),
+        },
+    }
+}
+
+/// Whether every pixel of the image is fully opaque
+fn is_opaque(img: &DynamicImage) -> bool {
+    match img {
+        DynamicImage::ImageLumaA8(buffer) => buffer.pixels().all(|p| p[1] == u8::MAX),
+        DynamicImage::ImageRgba8(buffer) => buffer.pixels().all(|p| p[3] == u8::MAX),
+        DynamicImage::ImageLumaA16(buffer) => buffer.pixels().all(|p| p[1] == u16::MAX),
+        DynamicImage::ImageRgba16(buffer) => buffer.pixels().all(|p| p[3] == u16::MAX),
+        DynamicImage::ImageRgba32F(buffer) => buffer.pixels().all(|p| p[3] >= 1.0),
+        _ => !img.color().has_alpha(),
+    }
+}
+
+/// Reduce the image to 8-bit with ordered dithering
+fn dither(img: &DynamicImage) -> RgbaImage {
+    let source = img.to_rgba16();
+
+    ImageBuffer::from_fn(source.width(), source.height(), |x, y| {
+        // Offset in the range of -0.5 to 0.5 of an 8-bit step
+        let threshold = (f32::from(BAYER[(y % 4) as usize][(x % 4) as usize]) + 0.5) / 16.0 - 0.5;
+
+        Rgba(source.get_pixel(x, y).0.map(|value| {
+            (f32::from(value) / 257.0 + threshold).round().clamp(0.0, 255.0) as u8
+        }))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{Luma, LumaA};
+    use super::*;
+
+    fn settings(bit_depth: Option<BitDepth>, strip_alpha: bool, dither: bool) -> DepthSettings {
+        DepthSettings { bit_depth, strip_alpha: Some(strip_alpha), dither: Some(dither) }
+    }
+
+    #[test]
+    fn strip_alpha_only_when_opaque() {
+        let opaque = DynamicImage::ImageRgba8(RgbaImage::from_pixel(2, 2, Rgba([10, 20, 30, 255])));
+        assert!(matches!(convert(opaque, &settings(None, true, false)), DynamicImage::ImageRgb8(_)));
+
+        let mut translucent = RgbaImage::from_pixel(2, 2, Rgba([10, 20, 30, 255]));
+        translucent.put_pixel(1, 1, Rgba([10, 20, 30, 254]));
+        let translucent = DynamicImage::ImageRgba8(translucent);
+        assert!(matches!(convert(translucent, &settings(None, true, false)), DynamicImage::ImageRgba8(_)));
+
+        let opaque = DynamicImage::ImageLumaA16(ImageBuffer::from_pixel(2, 2, LumaA([1000u16, u16::MAX])));
+        assert!(matches!(convert(opaque, &settings(None, true, false)), DynamicImage::ImageLuma16(_)));
+
+        let opaque = DynamicImage::ImageRgba8(RgbaImage::from_pixel(2, 2, Rgba([10, 20, 30, 255])));
+        assert!(matches!(convert(opaque, &settings(None, false, false)), DynamicImage::ImageRgba8(_)));
+    }
+
+    #[test]
+    fn dither_stays_near_truncation() {
+        let gradient = DynamicImage::ImageRgb16(ImageBuffer::from_fn(16, 16, |x, y| {
+            let value = (x * 16 + y) as u16 * 97;
+            image::Rgb([value, value / 2, u16::MAX - value])
+        }));
+        let source = gradient.to_rgb16();
+        let dithered = convert(gradient, &settings(Some(BitDepth::Eight), false, true));
+
+        let DynamicImage::ImageRgb8(dithered) = dithered else { panic!("expected 8-bit RGB") };
+        for (source, dithered) in source.pixels().zip(dithered.pixels()) {
+            for c in 0..3 {
+                let truncated = i32::from(source[c] / 257);
+                assert!((i32::from(dithered[c]) - truncated).abs() <= 1, "{} dithered to {}", source[c], dithered[c]);
+            }
+        }
+    }
+
+    #[test]
+    fn grayscale_stays_grayscale() {
+        let gray = DynamicImage::ImageLuma16(ImageBuffer::from_fn(4, 4, |x, y| Luma([(x * 4 + y) as u16 * 4000])));
+        let source = gray.to_luma16();
+
+        let DynamicImage::ImageLuma8(dithered) = convert(gray.clone(), &settings(Some(BitDepth::Eight), false, true)) else {
+            panic!("expected 8-bit grayscale");
+        };
+        for (source, dithered) in source.pixels().zip(dithered.pixels()) {
+            assert!((i32::from(dithered[0]) - i32::from(source[0] / 257)).abs() <= 1);
+        }
+
+        assert!(matches!(convert(gray, &settings(Some(BitDepth::Eight), false, false)), DynamicImage::ImageLuma8(_)));
+        let gray = DynamicImage::ImageLumaA8(ImageBuffer::from_pixel(2, 2, LumaA([100, 100])));
+        assert!(matches!(convert(gray, &settings(Some(BitDepth::Sixteen), true, false)), DynamicImage::ImageLumaA16(_)));
+    }
+}
@@ -0,0 +1,131 @@
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+use clap::ValueEnum;
+use serde::Deserialize;
+use image::{ColorType, DynamicImage, ImageEncoder, ImageFormat, ImageResult};
+use image::codecs::jpeg::JpegEncoder;
+use image::codecs::png::{CompressionType, FilterType, PngEncoder};
+
+/// Output format
+/// The image formats the converted files can be written as
+#[derive(ValueEnum, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputFormat {
+    Png,
+    Jpg,
+    Webp,
+    Tga,
+    Bmp,
+    Exr,
+}
+
+impl OutputFormat {
+    /// The file extension used for files written in this format
+    pub fn extension(&self) -> &'static str {
+        match self {
+            OutputFormat::Png => "png",
+            OutputFormat::Jpg => "jpg",
+            OutputFormat::Webp => "webp",
+            OutputFormat::Tga => "tga",
+            OutputFormat::Bmp => "bmp",
+            OutputFormat::Exr => "exr",
+        }
+    }
+}
+
+/// PNG compression level
+/// Maps directly onto the compression types offered by the PNG encoder
+#[derive(ValueEnum, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum PngCompression {
+    Fast,
+    Default,
+    Best,
+}
+
+impl From<PngCompression> for CompressionType {
+    fn from(value: PngCompression) -> Self {
+        match value {
+            PngCompression::Fast => CompressionType::Fast,
+            PngCompression::Default => CompressionType::Default,
+            PngCompression::Best => CompressionType::Best,
+        }
+    }
+}
+
+/// Encoder settings
+/// Format-specific settings which are applied when the output image is encoded.
+/// Settings which don't apply to the chosen format are ignored.
+pub struct EncoderSettings {
+    pub jpeg_quality: u8,
+    pub png_compression: PngCompression,
+}
+
+/// Save the image in the requested format
+/// The image is first converted to a color type the encoder supports, since
+/// for instance JPEG has no alpha channel and EXR only stores floating point data
+pub fn save_image(
+    img: &DynamicImage,
+    path: &Path,
+    format: OutputFormat,
+    settings: &EncoderSettings,
+) -> ImageResult<()> {
+    let img = prepare_for_format(img, format);
+
+    match format {
+        OutputFormat::Png => {
+            let encoder = PngEncoder::new_with_quality(
+                BufWriter::new(File::create(path)?),
+                settings.png_compression.into(),
+                FilterType::Adaptive,
+            );
+            encoder.write_image(img.as_bytes(), img.width(), img.height(), img.color())
+        },
+        OutputFormat::Jpg => {
+            let encoder = JpegEncoder::new_with_quality(
+                BufWriter::new(File::create(path)?),
+                settings.jpeg_quality,
+            );
+            encoder.write_image(img.as_bytes(), img.width(), img.height(), img.color())
+        },
+        OutputFormat::Webp => img.save_with_format(path, ImageFormat::WebP),
+        OutputFormat::Tga => img.save_with_format(path, ImageFormat::Tga),
+        OutputFormat::Bmp => img.save_with_format(path, ImageFormat::Bmp),
+        OutputFormat::Exr => img.save_with_format(path, ImageFormat::OpenExr),
+    }
+}
+
+/// Convert the image into a color type which the encoder of ``format`` accepts.
+/// Channels are preserved wherever the format allows it.
+fn prepare_for_format(img: &DynamicImage, format: OutputFormat) -> DynamicImage {
+    let color = img.color();
+    let has_alpha = color.has_alpha();
+    let is_gray = !color.has_color();
+
+    match format {
+        // PNG handles 8 and 16-bit data, but not floating point
+        OutputFormat::Png => match color {
+            ColorType::Rgb32F => DynamicImage::ImageRgb16(img.to_rgb16()),
+            ColorType::Rgba32F => DynamicImage::ImageRgba16(img.to_rgba16()),
+            _ => img.clone(),
+        },
+        // JPEG has no alpha channel, and only supports 8-bit data
+        OutputFormat::Jpg => match is_gray {
+            true => DynamicImage::ImageLuma8(img.to_luma8()),
+            false => DynamicImage::ImageRgb8(img.to_rgb8()),
+        },
+        // These encoders only accept 8-bit data
+        OutputFormat::Webp | OutputFormat::Tga | OutputFormat::Bmp => match (is_gray, has_alpha) {
+            (true, false) => DynamicImage::ImageLuma8(img.to_luma8()),
+            (true, true) => DynamicImage::ImageLumaA8(img.to_luma_alpha8()),
+            (false, false) => DynamicImage::ImageRgb8(img.to_rgb8()),
+            (false, true) => DynamicImage::ImageRgba8(img.to_rgba8()),
+        },
+        // EXR only stores floating point RGB(A)
+        OutputFormat::Exr => match has_alpha {
+            true => DynamicImage::ImageRgba32F(img.to_rgba32f()),
+            false => DynamicImage::ImageRgb32F(img.to_rgb32f()),
+        },
+    }
+}
@@ -1,19 +1,57 @@
-use std::{env, io};
+mod alpha;
+mod config;
+mod depth;
+mod format;
+mod normal;
+mod pack;
+mod pattern;
+mod rename;
+mod resize;
+mod workflow;
+
+use std::{env, fmt, io, thread};
+use std::ffi::OsString;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::fs;
-use std::path::{Path, PathBuf};
-use image::{DynamicImage, ImageResult};
+use std::path::{Component, Path, PathBuf};
+use std::process::{self, ExitCode};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc};
+use std::time::Duration;
+use image::{DynamicImage, ImageError, ImageResult};
 use image::io::Reader as ImageReader;
-use regex;
-use regex::Regex;
-use clap::Parser;
+use material::{
+    Color, CullMode, HintDictionary, HintMatch, MaterialError, MaterialSettings, NormalConvention, Preset,
+    TextureChannel, TextureReference, Transparency,
+};
+use clap::{CommandFactory, FromArgMatches, Parser};
+use clap::builder::PossibleValuesParser;
 use colored::Colorize;
+use crate::config::{ChannelSettings, Rule};
+use crate::format::{EncoderSettings, OutputFormat, PngCompression};
+use crate::normal::{NormalFilter, NormalSettings};
+use crate::pack::{PackMode, PackedChannels};
+use crate::pattern::{FileMatcher, MatchMode};
+use crate::rename::RenameContext;
+use crate::depth::{BitDepth, DepthSettings};
+use crate::resize::{PotMode, ResizeFilter, ResizeSettings};
+use regex::Regex;
 
 #[derive(Parser, Debug)]
 #[command(name = "Godot Image to Material Converter")]
 #[command(version = "0.1.5")]
 struct Options {
-    /// Regular expression applied on every file found
-    search_pattern: String,
+    /// Pattern applied on every file found (a regular expression, unless changed with --match)
+    /// When the config file sets it, all positional arguments are inputs instead
+    search_pattern: Option<String>,
+
+    /// Directories to search and/or files to convert
+    /// Defaults to the current working directory
+    inputs: Vec<PathBuf>,
+
+    /// Additional directory to search or file to convert (can be repeated)
+    #[arg(short, long = "input", value_name = "PATH")]
+    input: Vec<PathBuf>,
 
     /// Overwrite output files which already exists
     #[arg(short, long, default_value_t = false)]
@@ -32,33 +70,696 @@ struct Options {
     #[arg(short, long, default_value_t = false)]
     preview: bool,
 
-    /// Generate a Godot StandardMaterial3D based on the converted files
-    /// This requires that the filenames contain hints such as "albedo" or "normal"
+    /// Generate a Godot StandardMaterial3D (or ORMMaterial3D) based on the converted files
+    /// This requires that the filenames contain hints such as "albedo" or "normal".
+    /// A material is generated for each texture set, e.g. "brick.tres" and "wood.tres"
     #[arg(short, long, default_value_t = false)]
     material: bool,
+
+    /// Regular expression which picks the material group from the filename, instead of the
+    /// name left without channel and resolution tags. Uses the "group" (or first) capture group,
+    /// if there is one, e.g. "^([a-z]+)_"
+    #[arg(long, value_name = "REGEX")]
+    group_pattern: Option<String>,
+
+    /// Template for the material filenames, such as "{dir}_{group}"
+    /// Placeholders: {group} (the texture set) and {dir} (the name of the directory it was found in)
+    #[arg(long, value_name = "TEMPLATE", default_value = "{group}")]
+    material_name: String,
+
+    /// Directory the materials are written to, e.g. a shared materials directory
+    /// Defaults to the directory the textures are written to
+    #[arg(long, value_name = "DIR")]
+    material_dir: Option<PathBuf>,
+
+    /// How long to wait for Godot to import the converted files, before giving up on the materials
+    #[arg(long, value_name = "SECONDS", default_value_t = 100)]
+    import_timeout: u64,
+
+    /// Generate the materials without waiting for Godot to import the textures, e.g. in CI
+    /// The textures are referenced by their res:// path, based on the project.godot above them
+    #[arg(long)]
+    offline: bool,
+
+    /// Write the .import files of the converted textures, rather than leaving it to Godot, so normal
+    /// maps and roughness are imported correctly. Works with or without --material. Requires a
+    /// project.godot above the textures
+    #[arg(long)]
+    write_import: bool,
+
+    /// The image format of the output files
+    #[arg(short, long, value_enum, default_value_t = OutputFormat::Png)]
+    format: OutputFormat,
+
+    /// Quality of JPEG output (1-100)
+    #[arg(long, default_value_t = 90, value_parser = clap::value_parser!(u8).range(1..=100))]
+    jpeg_quality: u8,
+
+    /// Compression level of PNG output
+    #[arg(long, value_enum, default_value_t = PngCompression::Default)]
+    png_compression: PngCompression,
+
+    /// Search for files in subdirectories as well
+    /// With a destination, the directory structure is mirrored under the destination
+    #[arg(short, long, default_value_t = false)]
+    recursive: bool,
+
+    /// How many levels of subdirectories to descend into (used with --recursive)
+    #[arg(long)]
+    max_depth: Option<usize>,
+
+    /// Descend into symlinked directories (used with --recursive)
+    #[arg(long, default_value_t = false)]
+    follow_symlinks: bool,
+
+    /// Apply the search pattern on the relative path (e.g. "rock/albedo.tiff")
+    /// instead of only the filename
+    #[arg(long, default_value_t = false)]
+    match_path: bool,
+
+    /// How the search, --pattern and --exclude patterns are interpreted
+    #[arg(long = "match", value_enum, default_value_t = MatchMode::Regex)]
+    match_mode: MatchMode,
+
+    /// Additional search pattern (can be repeated)
+    /// Files matching any of the patterns are included
+    #[arg(long = "pattern", value_name = "PATTERN")]
+    patterns: Vec<String>,
+
+    /// Exclude files matching this pattern (can be repeated)
+    #[arg(short = 'x', long, value_name = "PATTERN")]
+    exclude: Vec<String>,
+
+    /// Number of files to convert concurrently (0 uses all available cores)
+    #[arg(short, long, default_value_t = 1)]
+    jobs: usize,
+
+    /// Template for the output filenames, such as "{material}_{channel}.{ext}"
+    /// Placeholders: {stem}, {stem_lower}, {ext}, {channel}, {material}, and capture
+    /// groups from the search pattern ({1}, {2}, ... or named groups)
+    #[arg(long, value_name = "TEMPLATE")]
+    rename: Option<String>,
+
+    /// Scale the longest side of larger images down to this size, e.g. 2048
+    #[arg(long, value_name = "PIXELS")]
+    max_size: Option<u32>,
+
+    /// Scale the images by a factor, e.g. 0.5 for half the size
+    #[arg(long, value_name = "FACTOR")]
+    scale: Option<f32>,
+
+    /// Round the dimensions to a power of two
+    #[arg(long, value_name = "MODE")]
+    pot: Option<PotMode>,
+
+    /// The resampling filter used when resizing (defaults to lanczos3)
+    #[arg(long, value_name = "FILTER")]
+    filter: Option<ResizeFilter>,
+
+    /// Force the bit depth of the output images, e.g. 16 for height maps
+    /// (defaults to that of the source)
+    #[arg(long, value_name = "BITS")]
+    bit_depth: Option<BitDepth>,
+
+    /// Drop the alpha channel of images which are fully opaque
+    #[arg(long)]
+    strip_alpha: bool,
+
+    /// Dither 16-bit images reduced to 8-bit, which avoids banding in gradients
+    #[arg(long)]
+    dither: bool,
+
+    /// Recognize an additional word as a material channel hint (can be repeated)
+    /// For instance "paint=albedo". Channels: albedo, normal, height, roughness, metallic, ao, emission, orm,
+    /// opacity (see --merge-opacity), gloss and specular (see --spec-gloss)
+    #[arg(long = "hint", value_name = "SYNONYM=CHANNEL")]
+    hints: Vec<String>,
+
+    /// Derive a normal map from the height map of materials which don't have one
+    #[arg(long)]
+    normal_from_height: bool,
+
+    /// The filter used to derive normal maps from height maps
+    #[arg(long, value_name = "FILTER", default_value = "sobel")]
+    normal_filter: NormalFilter,
+
+    /// How pronounced the normals derived from height maps are
+    #[arg(long, value_name = "STRENGTH", default_value_t = 1.0)]
+    normal_strength: f32,
+
+    /// Wrap around the edges when deriving normal maps, for tileable textures
+    #[arg(long)]
+    normal_wrap: bool,
+
+    /// Convert specular/glossiness maps into the metallic/roughness maps Godot uses
+    /// Glossiness is inverted into roughness, specular gives metallic and corrects the albedo
+    #[arg(long)]
+    spec_gloss: bool,
+
+    /// Merge opacity (or mask) maps into the alpha channel of the albedo map of their material
+    #[arg(long)]
+    merge_opacity: bool,
+
+    /// Pack separate maps into the channels of a single texture
+    /// "orm" packs occlusion, roughness and metallic into red, green and blue
+    #[arg(long, value_name = "MODE")]
+    pack: Option<PackMode>,
+
+    /// Use the naming conventions of a texture vendor to detect channels and resolution tags
+    #[arg(long, value_parser = PossibleValuesParser::new(Preset::NAMES))]
+    preset: Option<String>,
+
+    /// The color the emission texture is multiplied with, such as "#ff8800"
+    #[arg(long, default_value = "#ffffff", value_name = "HEX")]
+    emission_color: String,
+
+    /// The strength of the emission
+    #[arg(long, default_value_t = 1.0, value_name = "ENERGY")]
+    emission_energy: f32,
+
+    /// The channel of its texture a property reads (can be repeated)
+    /// For instance "roughness=g". Properties: roughness, metallic, ao. Channels: r, g, b, a, gray
+    #[arg(long = "texture-channel", value_name = "PROPERTY=CHANNEL")]
+    texture_channels: Vec<String>,
+
+    /// The transparency of materials whose albedo has transparent pixels
+    /// Defaults to scissor for cutouts, such as foliage, and blend for translucent surfaces
+    #[arg(long, value_parser = PossibleValuesParser::new(Transparency::NAMES))]
+    transparency: Option<String>,
+
+    /// The alpha below which pixels are discarded, when the transparency is scissor
+    #[arg(long, default_value_t = 0.5, value_name = "ALPHA")]
+    alpha_threshold: f32,
+
+    /// The faces which aren't rendered. Defaults to none for transparent materials
+    #[arg(long, value_parser = PossibleValuesParser::new(CullMode::NAMES))]
+    cull_mode: Option<String>,
+
+    /// The convention of normal maps which don't state it in their name, like "NormalDX" does
+    /// Godot expects OpenGL (gl), so DirectX (dx) normal maps get their green channel flipped.
+    /// Defaults to the convention of the preset, or gl
+    #[arg(long, value_name = "CONVENTION", value_parser = PossibleValuesParser::new(["dx", "gl"]))]
+    normal_convention: Option<String>,
+
+    /// Config file with defaults and per-pattern rules
+    /// Defaults to "gim.toml" in the working directory, if it exists
+    #[arg(short, long, value_name = "FILE")]
+    config: Option<PathBuf>,
+
+    /// Per-channel settings loaded from the config file
+    #[arg(skip)]
+    channel_settings: BTreeMap<String, ChannelSettings>,
+
+    /// Per-pattern rules loaded from the config file
+    #[arg(skip)]
+    rules: Vec<Rule>,
+
+    /// The built-in channel hints, extended with those from --hint and the config file
+    #[arg(skip)]
+    hint_dictionary: HintDictionary,
+
+    /// The material values which aren't derived from the textures
+    #[arg(skip)]
+    material_settings: MaterialSettings,
+
+    /// The compiled --group-pattern
+    #[arg(skip)]
+    group_regex: Option<Regex>,
+
+    /// The compiled search pattern, --pattern and --exclude patterns
+    #[arg(skip)]
+    file_matcher: FileMatcher,
+}
+
+impl Options {
+    /// All requested input paths, made absolute
+    /// If no inputs are given, the current working directory is used
+    fn input_paths(&self) -> Result<Vec<PathBuf>, ConversionError> {
+        let current_dir = env::current_dir()
+            .map_err(|error| ConversionError::Io { path: PathBuf::from("."), error })?;
+        let inputs: Vec<PathBuf> = self.inputs.iter().chain(self.input.iter()).cloned().collect();
+
+        if inputs.is_empty() {
+            return Ok(vec![current_dir]);
+        }
+
+        // Joining "." would leave "/./" in the middle of every path
+        Ok(inputs
+            .into_iter()
+            .map(|input| current_dir
+                .join(input)
+                .components()
+                .filter(|c| *c != Component::CurDir)
+                .collect())
+            .collect())
+    }
+
+    /// Build the matcher deciding which files are included, from the search
+    /// pattern, the additional patterns and the exclusions
+    fn build_file_matcher(&self) -> Result<FileMatcher, ConversionError> {
+        let compile = |option: &str, patterns: &[String]| -> Result<Vec<Regex>, ConversionError> {
+            patterns
+                .iter()
+                .map(|pattern| pattern::generate_regex(pattern, self.match_mode))
+                .collect::<Result<Vec<Regex>, String>>()
+                .map_err(|reason| ConversionError::InvalidOption { option: option.to_owned(), reason })
+        };
+
+        let mut include: Vec<String> = self.search_pattern.iter().cloned().collect();
+        include.extend(self.patterns.iter().cloned());
+
+        Ok(FileMatcher::new(compile("pattern", &include)?, compile("exclude", &self.exclude)?))
+    }
+
+    /// The number of worker threads used for the conversion
+    fn job_count(&self) -> usize {
+        if self.jobs == 0 {
+            return thread::available_parallelism().map_or(1, |n| n.get());
+        }
+
+        self.jobs
+    }
+
+    /// Build the hint dictionary, adding the vendor preset and the user-supplied
+    /// synonyms to the built-in ones
+    fn build_hint_dictionary(&self) -> Result<HintDictionary, ConversionError> {
+        let mut dictionary = match &self.preset {
+            Some(name) => {
+                let preset: Preset = name.parse()
+                    .map_err(|reason| ConversionError::InvalidPreset { name: name.clone(), reason })?;
+                HintDictionary::with_preset(preset)
+            },
+            None => HintDictionary::default(),
+        };
+
+        if let Some(name) = &self.normal_convention {
+            let convention: NormalConvention = name.parse()
+                .map_err(|reason| ConversionError::InvalidOption { option: String::from("normal-convention"), reason })?;
+            dictionary.set_normal_convention(convention);
+        }
+
+        for hint in &self.hints {
+            let invalid = |reason: String| ConversionError::InvalidHint { hint: hint.clone(), reason };
+            let (synonym, channel) = hint
+                .split_once('=')
+                .ok_or_else(|| invalid(String::from("expected SYNONYM=CHANNEL")))?;
+
+            dictionary.add(synonym.trim(), channel.trim()).map_err(invalid)?;
+        }
+
+        Ok(dictionary)
+    }
+
+    /// Compile the --group-pattern, if there is one
+    fn build_group_regex(&self) -> Result<Option<Regex>, ConversionError> {
+        self.group_pattern
+            .as_deref()
+            .map(Regex::new)
+            .transpose()
+            .map_err(|err| ConversionError::InvalidOption { option: String::from("group-pattern"), reason: err.to_string() })
+    }
+
+    /// Collect the values written to the material, besides the textures
+    fn build_material_settings(&self) -> Result<MaterialSettings, ConversionError> {
+        let emission_color: Color = self.emission_color.parse()
+            .map_err(|reason| ConversionError::InvalidOption { option: String::from("emission-color"), reason })?;
+
+        let mut texture_channels: HashMap<String, TextureChannel> = HashMap::new();
+        for texture_channel in &self.texture_channels {
+            let invalid = |reason: String| ConversionError::InvalidOption {
+                option: String::from("texture-channel"),
+                reason: format!("\"{}\": {}", texture_channel, reason),
+            };
+            let (property, channel) = texture_channel
+                .split_once('=')
+                .ok_or_else(|| invalid(String::from("expected PROPERTY=CHANNEL")))?;
+            let property = property.trim().to_lowercase();
+
+            if !matches!(property.as_str(), "roughness" | "metallic" | "ao") {
+                return Err(invalid(String::from("the property must be roughness, metallic or ao")));
+            }
+
+            texture_channels.insert(property, channel.parse().map_err(invalid)?);
+        }
+
+        let transparency: Option<Transparency> = self.transparency.as_deref().map(str::parse).transpose()
+            .map_err(|reason| ConversionError::InvalidOption { option: String::from("transparency"), reason })?;
+        let cull_mode: Option<CullMode> = self.cull_mode.as_deref().map(str::parse).transpose()
+            .map_err(|reason| ConversionError::InvalidOption { option: String::from("cull-mode"), reason })?;
+
+        if !(0.0..=1.0).contains(&self.alpha_threshold) {
+            return Err(ConversionError::InvalidOption {
+                option: String::from("alpha-threshold"),
+                reason: String::from("expected a value between 0 and 1"),
+            });
+        }
+
+        Ok(MaterialSettings {
+            emission_color,
+            emission_energy: self.emission_energy,
+            texture_channels,
+            transparency,
+            alpha_scissor_threshold: self.alpha_threshold,
+            cull_mode,
+        })
+    }
+
+    /// Check the scale and maximum size, given on the command line or in the config file
+    fn check_resize_settings(&self) -> Result<(), ConversionError> {
+        if let Some(scale) = self.scale {
+            resize::check_scale(scale)
+                .map_err(|reason| ConversionError::InvalidOption { option: String::from("scale"), reason })?;
+        }
+
+        if let Some(max_size) = self.max_size {
+            resize::check_max_size(max_size)
+                .map_err(|reason| ConversionError::InvalidOption { option: String::from("max-size"), reason })?;
+        }
+
+        Ok(())
+    }
+
+    /// Collect the resize settings of a file of ``channel``: the options, overridden
+    /// by the settings of the channel, overridden by the matching rule
+    fn resize_settings(&self, channel: Option<&str>, rule: Option<&Rule>) -> ResizeSettings {
+        let mut settings = ResizeSettings {
+            max_size: self.max_size,
+            scale: self.scale,
+            pot: self.pot,
+            filter: self.filter,
+        };
+
+        if let Some(channel_settings) = channel.and_then(|channel| self.channel_settings.get(channel)) {
+            settings = settings.merge(channel_settings.resize_settings());
+        }
+
+        if let Some(rule) = rule {
+            settings = settings.merge(rule.resize_settings());
+        }
+
+        settings
+    }
+
+    /// Collect the depth settings of a file of ``channel``: the options, overridden
+    /// by the settings of the channel, overridden by the matching rule
+    fn depth_settings(&self, channel: Option<&str>, rule: Option<&Rule>) -> DepthSettings {
+        let mut settings = DepthSettings {
+            bit_depth: self.bit_depth,
+            strip_alpha: Some(self.strip_alpha),
+            dither: Some(self.dither),
+        };
+
+        if let Some(channel_settings) = channel.and_then(|channel| self.channel_settings.get(channel)) {
+            settings = settings.merge(channel_settings.depth_settings());
+        }
+
+        if let Some(rule) = rule {
+            settings = settings.merge(rule.depth_settings());
+        }
+
+        settings
+    }
+
+    /// Collect the settings used to derive normal maps from height maps
+    fn normal_settings(&self) -> NormalSettings {
+        NormalSettings {
+            filter: self.normal_filter,
+            strength: self.normal_strength,
+            wrap: self.normal_wrap,
+        }
+    }
+
+    /// Collect the format-specific encoder settings
+    fn encoder_settings(&self) -> EncoderSettings {
+        EncoderSettings {
+            jpeg_quality: self.jpeg_quality,
+            png_compression: self.png_compression,
+        }
+    }
 }
 
-/// Error types for the ``convert_file`` method.
+/// Source file
+/// A file selected for conversion, along with the input root it was found under.
+/// The root is used to decide where the converted file is placed.
+///
+/// The format, destination, hint, texture channel, resize and depth settings and rename template
+/// come from the options, unless the rule in the config file which matches this file overrides them.
+/// The captures are the capture groups of the search pattern, used by the rename template.
+///
+/// A derived file doesn't exist on disk (or is replaced), but is built from other files.
+struct SourceFile {
+    path: PathBuf,
+    root: PathBuf,
+    format: OutputFormat,
+    destination: Option<String>,
+    hint: Option<String>,
+    rename: Option<String>,
+    channel: Option<TextureChannel>,
+    resize: ResizeSettings,
+    depth: DepthSettings,
+    captures: HashMap<String, String>,
+    rule: Option<Rule>,
+    derived: Option<Derived>,
+}
+
+impl SourceFile {
+    /// A file of ``channel`` built from ``source`` (e.g. a normal map from a height map), named ``stem``
+    /// It's placed and renamed like the source, with the settings of the channel.
+    fn derived_from(source: &SourceFile, stem: &str, channel: &str, derived: Derived, options: &Options) -> SourceFile {
+        SourceFile {
+            path: with_stem(&source.path, stem),
+            root: source.root.clone(),
+            format: source.format,
+            destination: source.destination.clone(),
+            hint: Some(channel.to_owned()),
+            rename: source.rename.clone(),
+            channel: None,
+            resize: options.resize_settings(Some(channel), None),
+            depth: options.depth_settings(Some(channel), None),
+            captures: source.captures.clone(),
+            rule: None,
+            derived: Some(derived),
+        }
+    }
+}
+
+/// Derived file
+/// Describes how a file is built from other files, rather than just converted
+enum Derived {
+    /// Occlusion, roughness and metallic packed into one texture
+    Packed(PackedChannels),
+    /// A normal map derived from a height map
+    NormalFromHeight(PathBuf),
+    /// A roughness map made by inverting a glossiness map
+    RoughnessFromGloss(PathBuf),
+    /// A metallic map approximated from a specular map, and the diffuse map if there is one
+    MetallicFromSpecular { specular: PathBuf, diffuse: Option<PathBuf> },
+    /// An albedo map approximated from a diffuse and a specular map
+    AlbedoFromSpecular { diffuse: PathBuf, specular: PathBuf },
+    /// An albedo map with an opacity map merged into its alpha channel
+    AlbedoWithOpacity { albedo: PathBuf, opacity: PathBuf },
+}
+
+impl Derived {
+    /// The files it's built from
+    fn sources(&self) -> Vec<&PathBuf> {
+        match self {
+            Derived::Packed(channels) => channels.sources(),
+            Derived::NormalFromHeight(height) => vec![height],
+            Derived::RoughnessFromGloss(gloss) => vec![gloss],
+            Derived::MetallicFromSpecular { specular, diffuse } => [Some(specular), diffuse.as_ref()]
+                .into_iter()
+                .flatten()
+                .collect(),
+            Derived::AlbedoFromSpecular { diffuse, specular } => vec![diffuse, specular],
+            Derived::AlbedoWithOpacity { albedo, opacity } => vec![albedo, opacity],
+        }
+    }
+
+    /// Whether the sources are replaced by the derived file, and thereby count as
+    /// converted (e.g. for --delete-sources). A height map stays in use next to its normal map.
+    fn replaces_sources(&self) -> bool {
+        !matches!(self, Derived::NormalFromHeight(_))
+    }
+
+    /// The tag the result is reported with
+    fn tag(&self) -> &'static str {
+        match self {
+            Derived::Packed(_) => "PACKED",
+            Derived::NormalFromHeight(_) => "GENERATED",
+            Derived::AlbedoWithOpacity { .. } => "MERGED",
+            _ => "CONVERTED",
+        }
+    }
+}
+
+/// Error types for the conversion pipeline.
 /// We want to handle errors differently, for instance when a file exists, it should
-/// not be excluded from the list which is passed onto the material generator
+/// not be excluded from the list which is passed onto the material generator.
+/// The remaining variants carry the context needed to report what went wrong.
 enum ConversionError {
-    FailedToDecode,
-    FailedToConvert,
-    FileExists,
+    FailedToDecode { path: PathBuf, reason: String },
+    FailedToConvert { path: PathBuf, reason: String },
+    FileExists { path: PathBuf },
+    DuplicateOutput { path: PathBuf, sources: Vec<PathBuf> },
+    Io { path: PathBuf, error: io::Error },
+    UnsupportedFormat { path: PathBuf },
+    NonUtf8Path { path: PathBuf },
+    ImportParse { path: PathBuf, reason: String },
+    Material { reason: String },
+    Config { path: PathBuf, reason: String },
+    Rename { path: PathBuf, reason: String },
+    InvalidHint { hint: String, reason: String },
+    InvalidPreset { name: String, reason: String },
+    InvalidOption { option: String, reason: String },
 }
 
-fn main() {
-    process(Options::parse());
+impl ConversionError {
+    /// Translate an error from the Image library, which occurred while
+    /// either decoding or encoding ``path``
+    fn from_image_error(path: &Path, err: ImageError, decoding: bool) -> ConversionError {
+        let path = path.to_path_buf();
+
+        match err {
+            ImageError::IoError(error) => ConversionError::Io { path, error },
+            ImageError::Unsupported(_) if decoding => ConversionError::UnsupportedFormat { path },
+            err if decoding => ConversionError::FailedToDecode { path, reason: err.to_string() },
+            err => ConversionError::FailedToConvert { path, reason: err.to_string() },
+        }
+    }
 }
 
-/// Generate the ``Regex`` instance based on the provided ``search_pattern``
-/// from the ``Options`` struct
-fn generate_filename_regex(pattern: String) -> Regex {
-    // Set up the regular expression pattern as a string
-    let full_pattern: String = format!(r"^{}$", pattern);
+impl From<MaterialError> for ConversionError {
+    fn from(err: MaterialError) -> Self {
+        match err {
+            MaterialError::ImportParse { path, reason } => ConversionError::ImportParse { path, reason },
+            err => ConversionError::Material { reason: err.to_string() },
+        }
+    }
+}
 
-    // Build the Regex instance based on the full_pattern string
-    Regex::new(&*full_pattern).expect("Invalid regex pattern")
+impl fmt::Display for ConversionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConversionError::FailedToDecode { path, reason } => {
+                write!(f, "Failed to decode {}: {}", path.display(), reason)
+            },
+            ConversionError::FailedToConvert { path, reason } => {
+                write!(f, "Failed to convert {}: {}", path.display(), reason)
+            },
+            ConversionError::FileExists { path } => write!(f, "File exists: {}", path.display()),
+            ConversionError::DuplicateOutput { path, sources } => {
+                let sources: Vec<String> = sources.iter().map(|source| display_name(source)).collect();
+                write!(f, "{} would be written to the same file: {}", sources.join(", "), path.display())
+            },
+            ConversionError::Io { path, error } => write!(f, "{}: {}", path.display(), error),
+            ConversionError::UnsupportedFormat { path } => {
+                write!(f, "Unsupported image format: {}", path.display())
+            },
+            ConversionError::NonUtf8Path { path } => {
+                write!(f, "Path is not valid UTF-8: {}", path.display())
+            },
+            ConversionError::ImportParse { path, reason } => {
+                write!(f, "Failed to parse {}: {}", path.display(), reason)
+            },
+            ConversionError::Material { reason } => write!(f, "Material: {}", reason),
+            ConversionError::Config { path, reason } => {
+                write!(f, "Invalid config file {}: {}", path.display(), reason)
+            },
+            ConversionError::Rename { path, reason } => {
+                write!(f, "Failed to rename {}: {}", path.display(), reason)
+            },
+            ConversionError::InvalidHint { hint, reason } => {
+                write!(f, "Invalid hint \"{}\": {}", hint, reason)
+            },
+            ConversionError::InvalidPreset { name, reason } => {
+                write!(f, "Invalid preset \"{}\": {}", name, reason)
+            },
+            ConversionError::InvalidOption { option, reason } => {
+                write!(f, "Invalid --{}: {}", option, reason)
+            },
+        }
+    }
+}
+
+fn main() -> ExitCode {
+    // The options are parsed in two steps, so we can tell which values
+    // were given on the command line, and which may come from the config file
+    let matches = Options::command().get_matches();
+    let mut options = Options::from_arg_matches(&matches).unwrap_or_else(|err| err.exit());
+
+    if let Err(err) = config::load(&mut options, &matches) {
+        eprintln!("[{}] {}", "FAILED".red(), err);
+        return ExitCode::FAILURE;
+    }
+
+    if let Err(err) = options.check_resize_settings() {
+        eprintln!("[{}] {}", "FAILED".red(), err);
+        return ExitCode::FAILURE;
+    }
+
+    match options.build_hint_dictionary() {
+        Ok(dictionary) => options.hint_dictionary = dictionary,
+        Err(err) => {
+            eprintln!("[{}] {}", "FAILED".red(), err);
+            return ExitCode::FAILURE;
+        },
+    }
+
+    match options.build_material_settings() {
+        Ok(settings) => options.material_settings = settings,
+        Err(err) => {
+            eprintln!("[{}] {}", "FAILED".red(), err);
+            return ExitCode::FAILURE;
+        },
+    }
+
+    match options.build_group_regex() {
+        Ok(regex) => options.group_regex = regex,
+        Err(err) => {
+            eprintln!("[{}] {}", "FAILED".red(), err);
+            return ExitCode::FAILURE;
+        },
+    }
+
+    // The template is checked up front, rather than once for every material
+    if let Err(reason) = rename::render_material_name(&options.material_name, "group", "dir") {
+        let err = ConversionError::InvalidOption { option: String::from("material-name"), reason };
+        eprintln!("[{}] {}", "FAILED".red(), err);
+        return ExitCode::FAILURE;
+    }
+
+    if options.search_pattern.is_none() {
+        eprintln!("[{}] No search pattern given, neither as argument nor in the config file",
+                  "FAILED".red());
+        return ExitCode::FAILURE;
+    }
+
+    match options.build_file_matcher() {
+        Ok(matcher) => options.file_matcher = matcher,
+        Err(err) => {
+            eprintln!("[{}] {}", "FAILED".red(), err);
+            return ExitCode::FAILURE;
+        },
+    }
+
+    process(options)
+}
+
+/// Print the failure right away, and keep it for the summary
+fn report_failure(failures: &mut Vec<ConversionError>, err: ConversionError) {
+    eprintln!("[{}] {}", "FAILED".red(), err);
+    failures.push(err);
+}
+
+/// The filename of ``path``, for display purposes
+fn display_name(path: &Path) -> String {
+    path.file_name().unwrap_or(path.as_os_str()).to_string_lossy().into_owned()
+}
+
+/// The filename of ``path`` without extension, which is where channel hints are detected
+fn file_stem(path: &Path) -> String {
+    path.file_stem().unwrap_or_default().to_string_lossy().into_owned()
 }
 
 /// Run the processing.
@@ -67,159 +768,623 @@ fn generate_filename_regex(pattern: String) -> Regex {
 ///
 /// Then, a number of checks are made, such as whether the file already exists.
 /// If all checks pass, the file will be converted.
-fn process(options: Options) {
-    let files = get_files(&options);
+///
+/// Failures don't stop the processing of the remaining files. Instead, they are
+/// listed in the summary at the end, and the exit code signals the failure.
+fn process(options: Options) -> ExitCode {
+    let mut failures: Vec<ConversionError> = Vec::new();
+    let mut files = get_files(&options, &mut failures);
+
+    if options.spec_gloss {
+        files = workflow::convert_spec_gloss(files, &options);
+    }
+
+    if options.merge_opacity {
+        files = alpha::merge_opacity(files, &options);
+    }
+
+    if options.normal_from_height {
+        files = normal::add_from_height(files, &options);
+    }
+
+    if options.pack == Some(PackMode::Orm) {
+        files = pack::group_orm(files, &options);
+    }
 
-    create_destination_directory(&options).expect("Failed to create destination");
+    files = drop_duplicate_outputs(files, &options, &mut failures);
+
+    if let Err(err) = create_destination_directory(&options) {
+        report_failure(&mut failures, err);
+        return ExitCode::FAILURE;
+    }
 
     // If file list is empty, we notify the user
     if files.is_empty() {
-        eprintln!("{} {}",
-                  "File list is empty.",
-                  "Review the search pattern and make sure you're in the right directory.");
+        eprintln!("File list is empty. \
+                   Review the search pattern and make sure you're in the right directory.");
     }
 
     // The list of converted (or existing conversion), which will be passed
-    // to the material generator
-    let mut converted_files: Vec<PathBuf> = Vec::new();
+    // to the material generator, along with the material they belong to
+    let mut converted_files: Vec<(Option<MaterialKey>, material::Texture)> = Vec::new();
 
     // List of successfully converted files (used to delete sources)
     let mut successful_conversions: Vec<PathBuf> = Vec::new();
 
-    // Iterate over each file and attempt to convert them
-    for path in files {
+    // The output files of this run, which are never deleted, even if they're sources too
+    let mut outputs: HashSet<PathBuf> = HashSet::new();
+
+    // Numbers for the summary
+    let mut converted_count: usize = 0;
+    let mut existing_count: usize = 0;
+
+    // Convert the files, and handle the results in the order of the file list
+    convert_files(&files, &options, |file, result| {
+        let path = file.path.clone();
+
+        if let Ok(new_path) | Err(ConversionError::FileExists { path: new_path }) = &result {
+            outputs.insert(new_path.clone());
+        }
+
         // Store the original filename
-        let original = path.file_name().unwrap().to_str().unwrap();
+        let original = display_name(&path);
+
+        match result {
+            Ok(new_path) if file.derived.is_some() => {
+                let derived = file.derived.as_ref().expect("Checked by the guard");
+                let sources: Vec<String> = derived.sources().into_iter().map(|source| display_name(source)).collect();
+                let tag = match options.preview {
+                    true => format!("{} {}", "PREVIEW".blue(), derived.tag().green()),
+                    false => format!("{}", derived.tag().green()),
+                };
 
-        match convert_file(&path, &options) {
+                println!("[{}] {} => {}", tag, sources.join(" + "), display_name(&new_path));
+                converted_count += 1;
+                converted_files.push((material_key(&file.path, &options), texture(file, new_path)));
+                if derived.replaces_sources() {
+                    successful_conversions.extend(derived.sources().into_iter().cloned());
+                }
+            },
             Ok(new_path) => {
                 if options.preview {
                     println!("[{} {}] {} => {}",
                              "PREVIEW".blue(),
                              "CONVERTED".green(),
                              original,
-                             new_path.file_name().unwrap().to_str().unwrap()
+                             display_name(&new_path)
                     );
                 } else {
                     println!("[{}] {}", "CONVERTED".green(), original)
                 }
-                converted_files.push(new_path);
+                if is_directx_normal_map(file, &options) {
+                    println!("[{}] {}: {} normal map converted to {}",
+                             "FLIPPED".cyan(),
+                             original,
+                             NormalConvention::DirectX,
+                             NormalConvention::OpenGl
+                    );
+                }
+                converted_count += 1;
+                converted_files.push((material_key(&file.path, &options), texture(file, new_path)));
                 successful_conversions.push(path);
             },
-            Err(ConversionError::FailedToDecode) => eprintln!("Failed to decode: {}", original),
-            Err(ConversionError::FailedToConvert) => eprintln!("Failed to convert: {}", original),
-            Err(ConversionError::FileExists) => {
-                let new_path = generate_new_filename(&path, &options.destination);
-                println!("[{}] {}", "EXISTS".yellow(), new_path.file_name().unwrap().to_str().unwrap());
-                converted_files.push(new_path);
-                successful_conversions.push(path);
+            Err(ConversionError::FileExists { path: new_path }) => {
+                println!("[{}] {}", "EXISTS".yellow(), display_name(&new_path));
+                existing_count += 1;
+                converted_files.push((material_key(&file.path, &options), texture(file, new_path)));
+                match &file.derived {
+                    Some(derived) if derived.replaces_sources() => {
+                        successful_conversions.extend(derived.sources().into_iter().cloned());
+                    },
+                    Some(_) => {},
+                    None => successful_conversions.push(path),
+                }
             },
+            Err(err) => report_failure(&mut failures, err),
+        }
+    });
+
+    if options.write_import && !options.preview {
+        for err in write_import_files(&options, &converted_files) {
+            report_failure(&mut failures, err);
         }
     }
 
     if options.material {
-        generate_godot_material(&options, converted_files);
+        for err in generate_godot_materials(&options, converted_files) {
+            report_failure(&mut failures, err);
+        }
     }
 
     if options.delete_sources {
-        delete_sources(&options, successful_conversions);
+        // A source can be used by several derived files, such as a specular map, and a
+        // source can be its own output, e.g. when a PNG file is converted to PNG
+        let mut seen: HashSet<PathBuf> = HashSet::new();
+        successful_conversions.retain(|path| !outputs.contains(path) && seen.insert(path.clone()));
+
+        for err in delete_sources(&options, successful_conversions) {
+            report_failure(&mut failures, err);
+        }
+    }
+
+    print_summary(converted_count, existing_count, &failures);
+
+    if failures.is_empty() {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
     }
 }
 
-fn delete_sources(options: &Options, files: Vec<PathBuf>) {
+/// Leave out the files whose output would collide with that of another file in this run,
+/// such as "a_albedo" and "b_albedo", which "--rename {channel}" both turns into "albedo.png"
+///
+/// Neither of them is converted, since one would overwrite the other (or be reported as
+/// existing, and have its source deleted), and parallel workers would write the same file.
+fn drop_duplicate_outputs(
+    files: Vec<SourceFile>,
+    options: &Options,
+    failures: &mut Vec<ConversionError>,
+) -> Vec<SourceFile> {
+    let mut targets: BTreeMap<PathBuf, Vec<PathBuf>> = BTreeMap::new();
+
+    // Files whose output path can't be generated fail when they're converted
+    for file in &files {
+        if let Ok(target) = generate_new_filename(file, options) {
+            targets.entry(target).or_default().push(file.path.clone());
+        }
+    }
+
+    let mut duplicates: HashSet<PathBuf> = HashSet::new();
+    for (path, sources) in targets.into_iter().filter(|(_, sources)| sources.len() > 1) {
+        duplicates.extend(sources.iter().cloned());
+        report_failure(failures, ConversionError::DuplicateOutput { path, sources });
+    }
+
+    files.into_iter().filter(|file| !duplicates.contains(&file.path)).collect()
+}
+
+/// Convert the files using the requested number of worker threads
+///
+/// Workers pick the next file from a shared counter, and send the result back.
+/// The results are handed to ``on_result`` in the order of ``files``, regardless of
+/// which worker finishes first, so the output and the lists built from it stay
+/// deterministic.
+fn convert_files<F>(files: &[SourceFile], options: &Options, mut on_result: F)
+where
+    F: FnMut(&SourceFile, Result<PathBuf, ConversionError>),
+{
+    let jobs = options.job_count().min(files.len());
+
+    // No need to spin up threads for a single worker
+    if jobs <= 1 {
+        for file in files {
+            on_result(file, convert_file(file, options));
+        }
+        return;
+    }
+
+    let next_file = AtomicUsize::new(0);
+    let (sender, receiver) = mpsc::channel();
+
+    thread::scope(|scope| {
+        for _ in 0..jobs {
+            let sender = sender.clone();
+            let next_file = &next_file;
+
+            scope.spawn(move || loop {
+                let index = next_file.fetch_add(1, Ordering::Relaxed);
+                let Some(file) = files.get(index) else {
+                    break;
+                };
+
+                if sender.send((index, convert_file(file, options))).is_err() {
+                    break;
+                }
+            });
+        }
+
+        // Only the workers hold senders now, so the receiver ends when they're done
+        drop(sender);
+
+        // Results arriving ahead of their turn wait here
+        let mut pending: HashMap<usize, Result<PathBuf, ConversionError>> = HashMap::new();
+        let mut next_result: usize = 0;
+
+        for (index, result) in receiver {
+            pending.insert(index, result);
+
+            while let Some(result) = pending.remove(&next_result) {
+                on_result(&files[next_result], result);
+                next_result += 1;
+            }
+        }
+    });
+}
+
+/// Print the number of converted and existing files, followed by the list of failures
+fn print_summary(converted_count: usize, existing_count: usize, failures: &[ConversionError]) {
+    println!("====");
+    println!("[{}] {} converted, {} existing, {} failed",
+             "SUMMARY".blue(),
+             converted_count,
+             existing_count,
+             failures.len());
+
+    for err in failures {
+        eprintln!("[{}] {}", "FAILED".red(), err);
+    }
+}
+
+/// Ask the user to confirm, and then delete the source files
+/// Returns the files which couldn't be deleted
+fn delete_sources(options: &Options, files: Vec<PathBuf>) -> Vec<ConversionError> {
     if options.preview {
         delete_sources_preview(&files);
-        return;
+        return Vec::new();
+    }
+
+    if files.is_empty() {
+        return Vec::new();
     }
 
     println!("====");
-    let mut count: i8 = 0;
-    for file in &files {
-        count += 1;
-        println!("[FILE {}/{}] {}", count, files.len(), file.to_str().unwrap());
+    for (index, file) in files.iter().enumerate() {
+        println!("[FILE {}/{}] {}", index + 1, files.len(), file.display());
     }
     println!("Delete these files? [Y/n]");
 
     // Await the user response
+    // If we can't read the answer, we treat it as a "no"
     let mut input = String::new();
-    io::stdin()
-        .read_line(&mut input)
-        .expect("Failed to read line");
+    io::stdin().read_line(&mut input).ok();
 
     // Inputs often arrive with white spacing (by the ENTER button)
     input = input.trim().to_owned();
 
     // The answer must be an exact uppercase "Y"
     if input == "Y" {
-        delete_source_files(&files);
+        delete_source_files(&files)
     } else {
         println!("[{}] Source files won't be deleted", "INFO".blue());
+        Vec::new()
     }
 }
 
-fn delete_source_files(files: &Vec<PathBuf>) {
+fn delete_source_files(files: &Vec<PathBuf>) -> Vec<ConversionError> {
+    let mut failures: Vec<ConversionError> = Vec::new();
+
     for file in files {
-        let filename = file.file_name().unwrap().to_str().unwrap();
-        println!("[{}] {}", "DELETED".purple(), filename);
-        fs::remove_file(file).expect(format!("Failed to delete {}", filename).as_str())
+        match fs::remove_file(file) {
+            Ok(()) => println!("[{}] {}", "DELETED".purple(), display_name(file)),
+            Err(error) => failures.push(ConversionError::Io { path: file.clone(), error }),
+        }
     }
+
+    failures
 }
 
 fn delete_sources_preview(files: &Vec<PathBuf>) {
     for file in files {
-        println!("[{} {}] {}", "PREVIEW".blue(), "DELETED".purple(), file.to_str().unwrap());
+        println!("[{} {}] {}", "PREVIEW".blue(), "DELETED".purple(), file.display());
     }
 }
 
-/// Retrieve the compiled material data and store it in a file
+/// Write the .import files of the converted files, per material (see ``material_key``), so
+/// roughness maps can be linked to the normal map of their material
+/// Returns the errors, so a failing material doesn't keep the others from being written.
+fn write_import_files(
+    options: &Options,
+    converted_files: &[(Option<MaterialKey>, material::Texture)],
+) -> Vec<ConversionError> {
+    // Files which aren't part of any material are written on their own
+    let mut groups: BTreeMap<MaterialKey, Vec<material::Texture>> = BTreeMap::new();
+    let mut ungrouped: Vec<Vec<material::Texture>> = Vec::new();
+
+    for (key, texture) in converted_files {
+        match key {
+            Some(key) => groups.entry(key.clone()).or_default().push(texture.clone()),
+            None => ungrouped.push(vec![texture.clone()]),
+        }
+    }
+
+    let mut failures: Vec<ConversionError> = Vec::new();
+
+    for textures in groups.into_values().chain(ungrouped) {
+        let written = material::write_import_files(
+            &textures,
+            &options.hint_dictionary,
+            &options.material_settings,
+            options.allow_overwrites,
+        );
+
+        match written {
+            Ok(written) => written
+                .iter()
+                .for_each(|path| println!("[{}] {}", "IMPORT".cyan(), display_name(path))),
+            Err(err) => failures.push(err.into()),
+        }
+    }
+
+    failures
+}
+
+/// Generate a material for each group of converted files, see ``material_key``
+/// Returns the errors, so a failing material doesn't keep the others from being generated.
+fn generate_godot_materials(
+    options: &Options,
+    converted_files: Vec<(Option<MaterialKey>, material::Texture)>,
+) -> Vec<ConversionError> {
+    // Files without any hint, or with a channel the material has no property for,
+    // are left out of the material, and listed for the user
+    let texture_channel = |texture: &material::Texture| -> Option<String> {
+        texture.hint.clone().or_else(|| {
+            options.hint_dictionary.detect(&file_stem(&texture.path)).map(|hint_match| hint_match.channel.to_owned())
+        })
+    };
+
+    let (textures, unmatched): (Vec<_>, Vec<_>) = converted_files
+        .into_iter()
+        .partition(|(_, texture)| texture_channel(texture).is_some_and(|channel| material::supports_channel(&channel)));
+
+    for (_, texture) in &unmatched {
+        match texture_channel(texture) {
+            Some(channel) => println!("[{}] {} ({} maps can't be used in the material, see {})",
+                                      "UNSUPPORTED".yellow(),
+                                      display_name(&texture.path),
+                                      channel,
+                                      match channel.as_str() {
+                                          "opacity" => "--merge-opacity",
+                                          _ => "--spec-gloss",
+                                      }),
+            None => println!("[{}] {} (not included in the material)",
+                             "NO HINT".yellow(),
+                             display_name(&texture.path)),
+        }
+    }
+
+    if textures.is_empty() {
+        return vec![ConversionError::Material {
+            reason: String::from("None of the files contain a channel hint"),
+        }];
+    }
+
+    if let (Some(dir), false) = (&options.material_dir, options.preview) {
+        if let Err(error) = fs::create_dir_all(dir) {
+            return vec![ConversionError::Io { path: dir.clone(), error }];
+        }
+    }
+
+    let groups = group_textures(textures, texture_channel);
+
+    let mut failures: Vec<ConversionError> = Vec::new();
+
+    // Groups whose materials would end up in the same file, such as two "material.tres"
+    // in the --material-dir, are reported rather than overwriting each other
+    let mut materials: BTreeMap<PathBuf, Vec<(MaterialKey, Vec<material::Texture>)>> = BTreeMap::new();
+    for (key, textures) in groups {
+        match material_path(options, &key, &textures) {
+            Ok(mat_path) => materials.entry(mat_path).or_default().push((key, textures)),
+            Err(err) => failures.push(err),
+        }
+    }
+
+    let materials: Vec<(PathBuf, Vec<material::Texture>)> = materials
+        .into_iter()
+        .filter_map(|(mat_path, mut groups)| match groups.len() {
+            1 => Some((mat_path, groups.remove(0).1)),
+            _ => {
+                let sources: Vec<PathBuf> = groups.into_iter().map(|((dir, _), _)| dir).collect();
+                failures.push(ConversionError::DuplicateOutput { path: mat_path, sources });
+                None
+            },
+        })
+        .collect();
+
+    // There's nothing to wait for in preview mode, since the textures aren't written
+    let (missing, cancelled): (HashSet<PathBuf>, bool) = match options.offline || options.preview {
+        true => (HashSet::new(), false),
+        false => match wait_for_import_files(options, &materials) {
+            Ok(()) => (HashSet::new(), false),
+            Err(MaterialError::ImportTimeout { missing }) => (missing.into_iter().collect(), false),
+            Err(MaterialError::ImportCancelled { missing }) => (missing.into_iter().collect(), true),
+            Err(err) => {
+                failures.push(err.into());
+                return failures;
+            },
+        },
+    };
+
+    for (mat_path, textures) in materials {
+        // Only the materials whose textures weren't all imported are left out
+        let group_missing: Vec<PathBuf> = textures
+            .iter()
+            .map(|texture| material::import_path_for(&texture.path))
+            .filter(|import_path| missing.contains(import_path))
+            .collect();
+
+        if !group_missing.is_empty() {
+            let err = match cancelled {
+                true => MaterialError::ImportCancelled { missing: group_missing },
+                false => MaterialError::ImportTimeout { missing: group_missing },
+            };
+            failures.push(ConversionError::Material {
+                reason: format!("{} not generated. {}", display_name(&mat_path), err),
+            });
+            continue;
+        }
+
+        if let Err(err) = generate_godot_material(options, &mat_path, textures) {
+            failures.push(err);
+        }
+    }
+
+    failures
+}
+
+/// Wait for Godot to import the textures of all materials, before any of them are generated
+///
+/// The first Ctrl+C cancels the wait, so the rest of the run (such as --delete-sources)
+/// still completes. A second one quits right away, as does any Ctrl+C after the wait.
+fn wait_for_import_files(
+    options: &Options,
+    materials: &[(PathBuf, Vec<material::Texture>)],
+) -> Result<(), MaterialError> {
+    let wait = material::ImportWait {
+        timeout: Duration::from_secs(options.import_timeout),
+        ..Default::default()
+    };
+
+    // The handler can't be removed, so it's told when the wait is over
+    let waiting = Arc::new(AtomicBool::new(true));
+    let handler_waiting = Arc::clone(&waiting);
+    let cancelled = Arc::clone(&wait.cancelled);
+    let _ = ctrlc::set_handler(move || {
+        if !handler_waiting.load(Ordering::SeqCst) || cancelled.swap(true, Ordering::SeqCst) {
+            process::exit(130);
+        }
+    });
+
+    let files: Vec<PathBuf> = materials
+        .iter()
+        .flat_map(|(_, textures)| textures.iter().map(|texture| texture.path.clone()))
+        .collect();
+
+    let result = material::wait_for_import_files(&files, &wait);
+    waiting.store(false, Ordering::SeqCst);
+    result
+}
+
+/// Group the textures into materials, see ``material_key``
+fn group_textures(
+    textures: Vec<(Option<MaterialKey>, material::Texture)>,
+    texture_channel: impl Fn(&material::Texture) -> Option<String>,
+) -> BTreeMap<MaterialKey, Vec<material::Texture>> {
+    // Sorted by directory and name, so the materials are generated in a predictable order
+    let mut groups: BTreeMap<MaterialKey, Vec<material::Texture>> = BTreeMap::new();
+
+    for (key, mut texture) in textures {
+        let Some(key) = key else {
+            println!("[{}] {} (doesn't match the group pattern)",
+                     "NO GROUP".yellow(),
+                     display_name(&texture.path));
+            continue;
+        };
+
+        // The transparency of the material follows the alpha of the albedo texture. Files which
+        // weren't written (in preview mode) count as opaque.
+        if texture_channel(&texture).as_deref() == Some("albedo") {
+            if let Ok(img) = decode_image(&texture.path) {
+                texture.alpha = alpha::coverage(&img);
+            }
+        }
+
+        groups.entry(key).or_default().push(texture);
+    }
+
+    groups
+}
+
+/// The path of the material file of a group, named after the --material-name template
+///
+/// The material is written next to the textures (the first of them, should a rule have put them
+/// in different directories), or in the --material-dir. ``{dir}`` is the name of the directory
+/// the textures were found in.
+fn material_path(
+    options: &Options,
+    (dir, group): &MaterialKey,
+    textures: &[material::Texture],
+) -> Result<PathBuf, ConversionError> {
+    // Paths with "..", such as "/textures/..", are named after the directory they point to
+    let dir_name: String = fs::canonicalize(dir)
+        .ok()
+        .as_deref()
+        .unwrap_or(dir)
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+
+    let name = rename::render_material_name(&options.material_name, group, &dir_name)
+        .map_err(|reason| ConversionError::InvalidOption { option: String::from("material-name"), reason })?;
+
+    Ok(match &options.material_dir {
+        Some(material_dir) => material_dir.join(name),
+        None => textures[0].path.with_file_name(name),
+    })
+}
+
+/// Retrieve the compiled material data of a group and store it in a file
 /// When in preview mode, instead show where the file would be located
-fn generate_godot_material(options: &Options, converted_files: Vec<PathBuf>) {
-    let mat_data: Result<String, String> = material::generate(converted_files);
-    let base_path = PathBuf::from("material.tres");
-    let mat_path = generate_path(&base_path, &options.destination);
-
-    if mat_data.is_err() {
-        eprintln!("{}", mat_data.err().unwrap())
-    } else if !options.allow_overwrites && mat_path.exists() {
-        println!("[{}] Material file (overwrite not allowed)",
-                 "EXISTS".yellow());
+fn generate_godot_material(
+    options: &Options,
+    mat_path: &Path,
+    textures: Vec<material::Texture>,
+) -> Result<(), ConversionError> {
+    let reference = match options.offline {
+        true => TextureReference::Path,
+        false => TextureReference::Uid,
+    };
+
+    // In preview mode, the textures aren't written, let alone imported by Godot, so
+    // the material can only be generated when it refers to them by path
+    let mat_data: String = match options.preview && reference == TextureReference::Uid {
+        true => String::new(),
+        false => material::generate(textures, &options.hint_dictionary, &options.material_settings, reference)?,
+    };
+
+    if !options.allow_overwrites && mat_path.exists() {
+        println!("[{}] Material file {} (overwrite not allowed)",
+                 "EXISTS".yellow(),
+                 display_name(mat_path));
     } else if options.preview {
         println!("[{} {}] Generated material: {}",
                  "PREVIEW".blue(),
                  "OK".green(),
-                 mat_path.to_str().unwrap());
+                 mat_path.display());
     } else {
-        fs::write(mat_path.clone(), mat_data.unwrap())
-            .expect("Failed to generate material");
-        println!("[{}] Generated material: {}", "OK".green(), mat_path.to_str().unwrap());
+        fs::write(mat_path, mat_data)
+            .map_err(|error| ConversionError::Io { path: mat_path.to_path_buf(), error })?;
+        println!("[{}] Generated material: {}", "OK".green(), mat_path.display());
     }
-}
 
-/// If the user has requested a destination directory, we will first
-/// check if that directory exists -- and if not, we will create it
-fn create_destination_directory(options: &Options) -> Result<(), String> {
-    let dest = &options.destination;
+    Ok(())
+}
 
-    // If not destination is requested, return OK
-    if dest.is_none() {
-        return Ok(());
+/// The texture passed to the material generator for the converted ``file``
+/// Its transparency is inspected later, once it's known to be an albedo texture.
+fn texture(file: &SourceFile, path: PathBuf) -> material::Texture {
+    material::Texture {
+        path,
+        hint: file.hint.clone(),
+        channel: file.channel,
+        alpha: material::Alpha::Opaque,
     }
+}
 
-    let dir: String = dest.clone().unwrap();
-    let dir_path: &Path = Path::new(&dir);
-
-    // If the directory already exists, return OK
-    if dir_path.is_dir() {
+/// If the user has requested a destination directory, we will first
+/// check if that directory exists (in each input root) -- and if not, we will create it
+fn create_destination_directory(options: &Options) -> Result<(), ConversionError> {
+    // If not destination is requested, return OK
+    let Some(dest) = &options.destination else {
         return Ok(());
-    }
+    };
 
     if options.preview {
         return Ok(());
     }
 
-    // Abort, if we failed to create the directory
-    if let Err(err) = fs::create_dir(dir_path) {
-        return Err(format!("Error creating directory: {}", err));
+    // Inputs which don't exist are reported by ``get_files``
+    for input in options.input_paths()?.into_iter().filter(|input| input.exists()) {
+        let dir_path: PathBuf = input_root(&input).join(dest);
+
+        // If the directory already exists, there's nothing to do
+        if dir_path.is_dir() {
+            continue;
+        }
+
+        // Abort, if we failed to create the directory
+        fs::create_dir_all(&dir_path)
+            .map_err(|error| ConversionError::Io { path: dir_path.clone(), error })?;
     }
 
     Ok(())
@@ -228,90 +1393,405 @@ fn create_destination_directory(options: &Options) -> Result<(), String> {
 /// Convert file
 /// The image is loaded into a ``DynamicImage`` instance, which can then be used
 /// to save the image as a new format
-fn convert_file(path: &PathBuf, options: &Options) -> Result<PathBuf, ConversionError> {
+fn convert_file(file: &SourceFile, options: &Options) -> Result<PathBuf, ConversionError> {
     let allow_overwrites = options.allow_overwrites;
-    let destination = &options.destination;
 
-    // Attempt to read the file
-    let img: ImageResult<DynamicImage> = ImageReader::open(path.clone()).unwrap().decode();
-
-    // If reading the file failed, we'll abort
-    if img.is_err() {
-        return Err(ConversionError::FailedToDecode);
-    }
-
-    // Generate the new filepath
-    let new_path: PathBuf = generate_new_filename(&path, &destination);
+    // Generate the new filepath, before spending any time on the image
+    let new_path: PathBuf = generate_new_filename(file, options)?;
 
     // If the path exists, and overwrites are not allowed, we abort
     if new_path.exists() && !allow_overwrites {
-        return Err(ConversionError::FileExists);
+        return Err(ConversionError::FileExists { path: new_path });
     }
 
-    // If in preview mode, we will abort here to avoid carrying
-    // out actual actions. Instead, we just return OK
+    // If in preview mode, we will abort here to avoid carrying out actual actions.
+    // Files which aren't derived are still decoded, so those which can't be read are reported.
     if options.preview {
-        return Ok(new_path.clone());
+        if file.derived.is_none() {
+            decode_image(&file.path)?;
+        }
+        return Ok(new_path);
+    }
+
+    // Attempt to read the file, or build it from the maps it is derived from
+    let mut img: DynamicImage = match &file.derived {
+        None => decode_image(&file.path)?,
+        Some(Derived::Packed(channels)) => pack::pack_orm(channels)?,
+        Some(Derived::NormalFromHeight(height)) => {
+            normal::from_height(&decode_image(height)?, &options.normal_settings())
+        },
+        Some(Derived::RoughnessFromGloss(gloss)) => workflow::roughness_from_gloss(&decode_image(gloss)?),
+        Some(Derived::MetallicFromSpecular { specular, diffuse }) => {
+            let diffuse = diffuse.as_deref().map(decode_image).transpose()?;
+            workflow::metallic_from_specular(&decode_image(specular)?, diffuse.as_ref())
+        },
+        Some(Derived::AlbedoFromSpecular { diffuse, specular }) => {
+            workflow::albedo_from_specular(&decode_image(diffuse)?, &decode_image(specular)?)
+        },
+        Some(Derived::AlbedoWithOpacity { albedo, opacity }) => {
+            alpha::with_opacity(&decode_image(albedo)?, &decode_image(opacity)?)
+        },
+    };
+
+    // Godot reads normal maps as OpenGL, so DirectX normal maps are flipped
+    if is_directx_normal_map(file, options) {
+        normal::flip_green(&mut img);
+    }
+
+    let img = resize::resize(img, &file.resize);
+    let img = depth::convert(img, &file.depth);
+
+    // Make sure the directory exists, since it may be a mirrored subdirectory
+    if let Some(parent) = new_path.parent() {
+        fs::create_dir_all(parent)
+            .map_err(|error| ConversionError::Io { path: parent.to_path_buf(), error })?;
     }
 
-    // Attempt to save the file (the changed extension will automatically
-    // make Image library encode in that format)
-    let res: ImageResult<()> = img.unwrap().save(new_path.clone());
+    // Attempt to save the file in the requested format and with the
+    // format-specific encoder settings
+    let res: ImageResult<()> = format::save_image(
+        &img,
+        &new_path,
+        file.format,
+        &options.encoder_settings(),
+    );
 
     // If saving failed, we abort
-    if res.is_err() {
-        return Err(ConversionError::FailedToConvert);
+    if let Err(err) = res {
+        return Err(ConversionError::from_image_error(&new_path, err, false));
     }
 
     Ok(new_path.clone())
 }
 
+/// Whether the file is a normal map which follows the DirectX convention, either
+/// according to its name (e.g. "NormalDX"), or the assumed convention
+fn is_directx_normal_map(file: &SourceFile, options: &Options) -> bool {
+    // Normal maps derived from height maps are generated as OpenGL
+    if matches!(file.derived, Some(Derived::NormalFromHeight(_))) {
+        return false;
+    }
+
+    let is_normal_map = channel_of(file, options).is_some_and(|(channel, _)| channel == "normal");
+
+    is_normal_map && options.hint_dictionary.detect_normal_convention(&file_stem(&file.path)) == NormalConvention::DirectX
+}
+
+/// Identifies the material a file belongs to: its directory, and the name of its texture set
+type MaterialKey = (PathBuf, String);
+
+/// The ``MaterialKey`` of the file at ``path``, which decides what "the same material" means,
+/// both for the material files and for the maps combined before that (e.g. with --pack orm)
+///
+/// Files belong to the same material when they're in the same directory, and their texture set has
+/// the same name. With a --group-pattern, that's the "group" capture group, the first capture group,
+/// or the whole match, in that order, and ``None`` if the pattern doesn't match. Otherwise, it's the
+/// filename without channel, resolution and vendor tags, e.g. "Brick_4K_Color" is in "brick".
+/// Files named after just the channel, e.g. "albedo.png", are in "material".
+fn material_key(path: &Path, options: &Options) -> Option<MaterialKey> {
+    let parent: PathBuf = path.parent().map(Path::to_path_buf).unwrap_or_default();
+    let stem = file_stem(path);
+
+    if let Some(regex) = &options.group_regex {
+        let captures = regex.captures(&stem)?;
+        let group = captures.name("group").or_else(|| captures.get(1)).or_else(|| captures.get(0))?;
+        return Some((parent, group.as_str().to_owned())).filter(|(_, group)| !group.is_empty());
+    }
+
+    let hint_match = options.hint_dictionary.detect(&stem);
+    let group = rename::material_name(&stem, hint_match.as_ref(), &options.hint_dictionary);
+
+    match group.is_empty() {
+        true => Some((parent, String::from("material"))),
+        false => Some((parent, group)),
+    }
+}
+
+/// The channel of the file, such as "albedo", and where the hint is found in its filename
+///
+/// An explicit hint from a rule wins over the one in the filename. Either way, the hint match
+/// is the one found in the filename, if any, which is replaced when the file is renamed.
+/// This decides the channel for every step, from the per-channel settings to the material.
+fn channel_of(file: &SourceFile, options: &Options) -> Option<(String, Option<HintMatch>)> {
+    let hint_match = options.hint_dictionary.detect(&file_stem(&file.path));
+
+    match &file.hint {
+        Some(hint) => Some((hint.to_lowercase(), hint_match)),
+        None => hint_match.map(|hint_match| (hint_match.channel.to_owned(), Some(hint_match))),
+    }
+}
+
+/// Replace the channel hint in ``stem`` with another ``channel`` (given in lowercase),
+/// following the casing of the hint: "disp" becomes "normal", "HEIGHT" becomes "NORMAL",
+/// and "Height" becomes "Normal"
+fn replace_hint(stem: &str, hint_match: &HintMatch, channel: &str) -> String {
+    let hint = &stem[hint_match.start..hint_match.end];
+    let replacement = if hint.chars().all(|c| !c.is_uppercase()) {
+        channel.to_owned()
+    } else if hint.chars().all(|c| !c.is_lowercase()) {
+        channel.to_uppercase()
+    } else {
+        channel[..1].to_uppercase() + &channel[1..]
+    };
+
+    format!("{}{}{}", &stem[..hint_match.start], replacement, &stem[hint_match.end..])
+}
+
+/// The ``path`` with another ``stem``, keeping its extension
+/// Unlike ``with_file_name`` followed by ``with_extension``, this keeps the dots in
+/// the stem, so "Metal.002_AO.png" can become "Metal.002_ORM.png".
+fn with_stem(path: &Path, stem: &str) -> PathBuf {
+    let mut name = OsString::from(stem);
+    if let Some(extension) = path.extension() {
+        name.push(".");
+        name.push(extension);
+    }
+
+    path.with_file_name(name)
+}
+
+/// Read and decode the image at ``path``
+fn decode_image(path: &Path) -> Result<DynamicImage, ConversionError> {
+    ImageReader::open(path)
+        .map_err(|error| ConversionError::Io { path: path.to_path_buf(), error })?
+        .decode()
+        .map_err(|err| ConversionError::from_image_error(path, err, true))
+}
+
 /// Retrieves the list of files according to ``search_pattern``.
-/// The regular expression for matching filenames is generated witht ``generate_filename_regex``.
-/// Then the files of the current working directory are loaded, and afterward
-/// filtered using the generated ``Regex``.
-fn get_files(options: &Options) -> Vec<PathBuf> {
-    // Generate the Regex instance based on the search pattern provided by the user
-    let regex = generate_filename_regex(options.search_pattern.clone());
-
-    // Load current direction and list of files
-    let current_dir = env::current_dir().expect("Failed to retrieve directory");
-    let files = fs::read_dir(&current_dir).expect("Failed to read files in directory");
-
-    // Return list of files filtered by the regular expression instance
+/// The files of each input directory (and its subdirectories, in recursive mode)
+/// are loaded, and afterward filtered using the matcher, which is built from the
+/// search pattern, additional patterns and exclusions (see ``build_file_matcher``).
+///
+/// Files given explicitly as inputs are always included. Inputs that can't be
+/// read, and files whose names can't be matched, are added to ``failures``.
+fn get_files(options: &Options, failures: &mut Vec<ConversionError>) -> Vec<SourceFile> {
+    // The rules from the config file always use glob patterns, which are
+    // validated when the config file is loaded
+    let rules: Vec<(Regex, &Rule)> = options.rules
+        .iter()
+        .filter_map(|rule| Some((pattern::generate_regex(&rule.pattern, MatchMode::Glob).ok()?, rule)))
+        .collect();
+
+    let inputs = match options.input_paths() {
+        Ok(inputs) => inputs,
+        Err(err) => {
+            report_failure(failures, err);
+            return Vec::new();
+        },
+    };
+
+    let mut files: Vec<SourceFile> = Vec::new();
+
+    for input in inputs {
+        if input.is_file() {
+            files.push(source_file(input.clone(), input_root(&input), &rules, options));
+            continue;
+        }
+
+        // Load the (possibly nested) list of files in the input directory. The output
+        // of earlier runs isn't converted again, so the destinations count as visited.
+        let mut found: Vec<PathBuf> = Vec::new();
+        let mut visited: HashSet<PathBuf> = destination_directories(&input, options);
+        collect_files(&input, 0, options, &mut visited, &mut found, failures);
+
+        // Keep the files whose basename/filename (or relative path) matches the pattern
+        for path in found {
+            let Some(subject) = match_subject(&path, &input, options) else {
+                report_failure(failures, ConversionError::NonUtf8Path { path });
+                continue;
+            };
+
+            if let Some(captures) = options.file_matcher.captures(&subject) {
+                let mut file = source_file(path, input.clone(), &rules, options);
+                file.captures = captures;
+                files.push(file);
+            }
+        }
+    }
+
+    // Directory listings come in no particular order, so we sort them
+    // to make the output predictable. Overlapping inputs may find the same file twice.
+    files.sort_by(|a, b| a.path.cmp(&b.path));
+    files.dedup_by(|a, b| a.path == b.path);
     files
-        .filter_map(|entry| {
-            entry.ok().and_then(|dir_entry| {
-                let path = dir_entry.path();
-
-                // If path is a file and its basename/filename matches the pattern
-                // we want to keep it in the list of files
-                if path.is_file() && regex.is_match(path.file_name().unwrap().to_str().unwrap()) {
-                    Some(path)
-                } else {
-                    None
-                }
-            })
-        })
+}
+
+/// Create the ``SourceFile``, applying the first matching rule, if any
+fn source_file(path: PathBuf, root: PathBuf, rules: &[(Regex, &Rule)], options: &Options) -> SourceFile {
+    let subject = match_subject(&path, &root, options).unwrap_or_default();
+    let rule = rules
+        .iter()
+        .find(|(regex, _)| regex.is_match(&subject))
+        .map(|(_, rule)| *rule);
+
+    let mut file = SourceFile {
+        format: rule.and_then(|rule| rule.format).unwrap_or(options.format),
+        destination: rule
+            .and_then(|rule| rule.destination.clone())
+            .or_else(|| options.destination.clone()),
+        hint: rule.and_then(|rule| rule.hint.clone()),
+        resize: ResizeSettings::default(),
+        depth: DepthSettings::default(),
+        // The channels of the rules are validated when the config file is loaded
+        channel: rule
+            .and_then(|rule| rule.channel.as_ref())
+            .and_then(|channel| channel.parse().ok()),
+        rename: rule
+            .and_then(|rule| rule.rename.clone())
+            .or_else(|| options.rename.clone()),
+        captures: HashMap::new(),
+        rule: rule.cloned(),
+        derived: None,
+        path,
+        root,
+    };
+
+    // The channel decides which of the per-channel settings apply
+    let channel: Option<String> = channel_of(&file, options).map(|(channel, _)| channel);
+    file.resize = options.resize_settings(channel.as_deref(), rule);
+    file.depth = options.depth_settings(channel.as_deref(), rule);
+    file
+}
+
+/// Collect the files of ``dir``, and (in recursive mode) descend into its
+/// subdirectories until ``max_depth`` is reached.
+///
+/// Symlinked directories are only followed when requested, and every directory
+/// is only visited once, so symlink loops can't send us in circles.
+///
+/// Directories which can't be read are added to ``failures``, and the others are still searched.
+fn collect_files(
+    dir: &Path,
+    depth: usize,
+    options: &Options,
+    visited: &mut HashSet<PathBuf>,
+    files: &mut Vec<PathBuf>,
+    failures: &mut Vec<ConversionError>,
+) {
+    // Skip directories we've already been through
+    if !visited.insert(dir.canonicalize().unwrap_or(dir.to_path_buf())) {
+        return;
+    }
+
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(error) => {
+            report_failure(failures, ConversionError::Io { path: dir.to_path_buf(), error });
+            return;
+        },
+    };
+    let may_descend = options.recursive && !matches!(options.max_depth, Some(max) if depth >= max);
+
+    for entry in entries.flatten() {
+        let path = entry.path();
+
+        if path.is_file() {
+            files.push(path);
+        } else if path.is_dir() && may_descend {
+            let is_symlink = entry.file_type().is_ok_and(|t| t.is_symlink());
+            if !is_symlink || options.follow_symlinks {
+                collect_files(&path, depth + 1, options, visited, files, failures);
+            }
+        }
+    }
+}
+
+/// The destination directories below ``root``, which are skipped when collecting files
+/// The root itself is never skipped, even if it's the destination.
+fn destination_directories(root: &Path, options: &Options) -> HashSet<PathBuf> {
+    let root_dir = root.canonicalize().unwrap_or(root.to_path_buf());
+
+    options.destination
+        .iter()
+        .chain(options.rules.iter().filter_map(|rule| rule.destination.as_ref()))
+        .filter_map(|dest| root.join(dest).canonicalize().ok())
+        .filter(|dir| *dir != root_dir)
         .collect()
 }
 
+/// The string the search pattern is matched against: either the filename, or the
+/// path relative to the root (always using forward slashes)
+///
+/// Returns ``None`` if the name isn't valid UTF-8.
+fn match_subject(path: &Path, root: &Path, options: &Options) -> Option<String> {
+    if options.match_path {
+        let relative = path.strip_prefix(root).unwrap_or(path);
+        return relative
+            .components()
+            .map(|c| c.as_os_str().to_str())
+            .collect::<Option<Vec<&str>>>()
+            .map(|components| components.join("/"));
+    }
+
+    path.file_name()?.to_str().map(|name| name.to_owned())
+}
+
+/// The root directory of an input path: the directory itself, or
+/// the parent directory for files
+fn input_root(input: &Path) -> PathBuf {
+    match (input.is_file(), input.parent()) {
+        (true, Some(parent)) => parent.to_path_buf(),
+        _ => input.to_path_buf(),
+    }
+}
+
 /// Generates the output filename, based on options/configuration and
-/// the input filename.
-fn generate_new_filename(current: &PathBuf, destination: &Option<String>) -> PathBuf {
-    return generate_path(current, destination).with_extension("png");
+/// the input filename. The extension is decided by the requested output format.
+fn generate_new_filename(file: &SourceFile, options: &Options) -> Result<PathBuf, ConversionError> {
+    let path = generate_path(&file.path, &file.root, &file.destination, options)?;
+
+    let Some(template) = &file.rename else {
+        return Ok(path.with_extension(file.format.extension()));
+    };
+
+    // The channel is either given by a rule, or detected from the filename
+    let stem = file_stem(&file.path);
+    let (channel, hint_match) = match channel_of(file, options) {
+        Some((channel, hint_match)) => (Some(channel), hint_match),
+        None => (None, None),
+    };
+
+    let context = RenameContext {
+        stem: &stem,
+        ext: file.format.extension(),
+        channel: channel.as_deref(),
+        hint_match: hint_match.as_ref(),
+        dictionary: &options.hint_dictionary,
+        captures: &file.captures,
+    };
+
+    let name = rename::render(template, &context)
+        .map_err(|reason| ConversionError::Rename { path: file.path.clone(), reason })?;
+
+    Ok(path.with_file_name(name))
 }
 
-fn generate_path(current: &PathBuf, destination: &Option<String>) -> PathBuf {
-    let mut path = current.clone();
+/// The path of the output file, placed in the destination directory, if there is one
+fn generate_path(
+    current: &Path,
+    root: &Path,
+    destination: &Option<String>,
+    options: &Options,
+) -> Result<PathBuf, ConversionError> {
+    let Some(dest) = destination else {
+        return Ok(current.to_path_buf());
+    };
 
-    // If destination is requested, we insert the directory name between the filename
-    // and spot before the filename in the original path
-    if destination.is_some() {
-        path.pop();
-        path.push(destination.clone().unwrap());
-        path.push(current.file_name().unwrap());
+    // In recursive mode, the directory structure below the root is mirrored
+    // under the destination directory
+    if options.recursive {
+        if let Ok(relative) = current.strip_prefix(root) {
+            return Ok(root.join(dest).join(relative));
+        }
     }
 
-    path
+    // Otherwise, we insert the directory name between the filename
+    // and spot before the filename in the original path
+    let name = current.file_name().ok_or_else(|| ConversionError::FailedToConvert {
+        path: current.to_path_buf(),
+        reason: String::from("the path has no filename"),
+    })?;
+
+    Ok(current.with_file_name(dest).join(name))
 }
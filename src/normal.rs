@@ -0,0 +1,260 @@
+use std::collections::HashSet;
+use clap::ValueEnum;
+use serde::Deserialize;
+use image::{DynamicImage, ImageBuffer, Rgb, RgbImage};
+use material::HintMatch;
+use crate::{channel_of, file_stem, material_key, replace_hint, Derived, MaterialKey, Options, SourceFile};
+
+/// Flip the green channel of a normal map, which converts between the DirectX (Y-)
+/// and OpenGL (Y+) conventions. The bit depth of the image is preserved.
+pub fn flip_green(img: &mut DynamicImage) {
+    match img {
+        DynamicImage::ImageRgb8(buffer) => buffer.pixels_mut().for_each(|p| p[1] = u8::MAX - p[1]),
+        DynamicImage::ImageRgba8(buffer) => buffer.pixels_mut().for_each(|p| p[1] = u8::MAX - p[1]),
+        DynamicImage::ImageRgb16(buffer) => buffer.pixels_mut().for_each(|p| p[1] = u16::MAX - p[1]),
+        DynamicImage::ImageRgba16(buffer) => buffer.pixels_mut().for_each(|p| p[1] = u16::MAX - p[1]),
+        DynamicImage::ImageRgb32F(buffer) => buffer.pixels_mut().for_each(|p| p[1] = 1.0 - p[1]),
+        DynamicImage::ImageRgba32F(buffer) => buffer.pixels_mut().for_each(|p| p[1] = 1.0 - p[1]),
+        // Grayscale has no green channel to flip, so the image is made RGB first
+        _ => {
+            *img = DynamicImage::ImageRgba8(img.to_rgba8());
+            flip_green(img);
+        },
+    }
+}
+
+/// Normal filter
+/// The edge detection kernels a normal map can be derived from a height map with.
+/// Scharr is more rotationally accurate, Sobel is the classic choice.
+#[derive(ValueEnum, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum NormalFilter {
+    Sobel,
+    Scharr,
+}
+
+impl NormalFilter {
+    /// The weights of the outer and center taps of the kernel, scaled so both
+    /// filters give gradients of the same magnitude (that of Sobel)
+    fn weights(&self) -> (f32, f32) {
+        match self {
+            NormalFilter::Sobel => (1.0, 2.0),
+            NormalFilter::Scharr => (3.0 / 4.0, 10.0 / 4.0),
+        }
+    }
+}
+
+/// Normal settings
+/// How normal maps are derived from height maps
+pub struct NormalSettings {
+    pub filter: NormalFilter,
+    /// Multiplies the slopes, so higher values give more pronounced normals
+    pub strength: f32,
+    /// Sample across the edges, so tileable textures don't get seams
+    pub wrap: bool,
+}
+
+/// Derive a tangent-space normal map (OpenGL convention) from a height map,
+/// where white is high. 16-bit and floating point height maps give a 16-bit normal map.
+pub fn from_height(height: &DynamicImage, settings: &NormalSettings) -> DynamicImage {
+    let heights = height.to_luma32f();
+    let (width, height_px) = heights.dimensions();
+    let (outer, center) = settings.filter.weights();
+
+    // Look up the height at an offset, either wrapping around or clamping at the edges
+    let sample = |x: u32, y: u32, dx: i64, dy: i64| -> f32 {
+        let (w, h) = (i64::from(width), i64::from(height_px));
+        let (mut sx, mut sy) = (i64::from(x) + dx, i64::from(y) + dy);
+        if settings.wrap {
+            sx = sx.rem_euclid(w);
+            sy = sy.rem_euclid(h);
+        } else {
+            sx = sx.clamp(0, w - 1);
+            sy = sy.clamp(0, h - 1);
+        }
+        heights.get_pixel(sx as u32, sy as u32)[0]
+    };
+
+    let normal_at = |x: u32, y: u32| -> [f32; 3] {
+        let gradient_x = outer * (sample(x, y, 1, -1) - sample(x, y, -1, -1))
+            + center * (sample(x, y, 1, 0) - sample(x, y, -1, 0))
+            + outer * (sample(x, y, 1, 1) - sample(x, y, -1, 1));
+        let gradient_y = outer * (sample(x, y, -1, 1) - sample(x, y, -1, -1))
+            + center * (sample(x, y, 0, 1) - sample(x, y, 0, -1))
+            + outer * (sample(x, y, 1, 1) - sample(x, y, 1, -1));
+
+        // The image's y axis points down, while the green channel points up
+        let (nx, ny, nz) = (-gradient_x * settings.strength, gradient_y * settings.strength, 1.0);
+        let length = (nx * nx + ny * ny + nz * nz).sqrt();
+
+        [nx / length * 0.5 + 0.5, ny / length * 0.5 + 0.5, nz / length * 0.5 + 0.5]
+    };
+
+    match height.color().bytes_per_pixel() / height.color().channel_count() {
+        1 => DynamicImage::ImageRgb8(RgbImage::from_fn(width, height_px, |x, y| {
+            Rgb(normal_at(x, y).map(|v| (v * f32::from(u8::MAX)).round() as u8))
+        })),
+        _ => DynamicImage::ImageRgb16(ImageBuffer::from_fn(width, height_px, |x, y| {
+            Rgb(normal_at(x, y).map(|v| (v * f32::from(u16::MAX)).round() as u16))
+        })),
+    }
+}
+
+/// Add a normal map, derived from the height map, to every material which has
+/// a height map but no normal map
+///
+/// Height and normal maps are paired up by their ``material_key``. The normal map is named
+/// after the height map, with "Normal" in place of the hint ("Rock_Height" becomes "Rock_Normal",
+/// and "rock_disp" becomes "rock_normal"). Height maps without a hint in their name (whose
+/// channel comes from a rule) get "_normal" appended ("slab" becomes "slab_normal").
+pub fn add_from_height(mut files: Vec<SourceFile>, options: &Options) -> Vec<SourceFile> {
+    let mut heights: Vec<(MaterialKey, usize, Option<HintMatch>)> = Vec::new();
+    let mut normals: HashSet<MaterialKey> = HashSet::new();
+
+    for (index, file) in files.iter().enumerate() {
+        let (Some((channel, hint_match)), Some(key)) = (channel_of(file, options), material_key(&file.path, options)) else {
+            continue;
+        };
+
+        match channel.as_str() {
+            "height" => heights.push((key, index, hint_match)),
+            "normal" => { normals.insert(key); },
+            _ => {},
+        }
+    }
+
+    let mut derived: Vec<SourceFile> = Vec::new();
+
+    for (key, index, hint_match) in heights {
+        // Only one normal map per material, even if there are several height maps
+        if !normals.insert(key) {
+            continue;
+        }
+
+        let file = &files[index];
+        let stem = file_stem(&file.path);
+        let normal_stem = match &hint_match {
+            Some(hint_match) => replace_hint(&stem, hint_match, "normal"),
+            None => format!("{}_normal", stem),
+        };
+
+        let normal = Derived::NormalFromHeight(file.path.clone());
+        derived.push(SourceFile::derived_from(file, &normal_stem, "normal", normal, options));
+    }
+
+    files.append(&mut derived);
+    files.sort_by(|a, b| a.path.cmp(&b.path));
+    files
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+    use clap::Parser;
+    use regex::Regex;
+    use crate::config::Rule;
+    use crate::source_file;
+    use super::*;
+
+    /// The files as ``get_files`` would find them in ``/textures``, with the rules applied
+    fn files(names: &[&str], rules: &[(Regex, &Rule)], options: &Options) -> Vec<SourceFile> {
+        names
+            .iter()
+            .map(|name| source_file(Path::new("/textures").join(name), PathBuf::from("/textures"), rules, options))
+            .collect()
+    }
+
+    /// The filenames of the files
+    fn names(files: &[SourceFile]) -> Vec<String> {
+        files.iter().map(|file| file.path.file_name().unwrap().to_string_lossy().into_owned()).collect()
+    }
+
+    #[test]
+    fn add_from_height_keeps_dots_in_the_stem() {
+        let options = Options::parse_from(["gim", ".*"]);
+        let files = add_from_height(files(&["Rock.001_Height.ppm", "Rock.002_Height.ppm"], &[], &options), &options);
+
+        assert_eq!(names(&files), [
+            "Rock.001_Height.ppm",
+            "Rock.001_Normal.ppm",
+            "Rock.002_Height.ppm",
+            "Rock.002_Normal.ppm",
+        ]);
+    }
+
+    #[test]
+    fn add_from_height_uses_the_hint_of_a_rule() {
+        let options = Options::parse_from(["gim", ".*"]);
+        let lower: Rule = toml::from_str("pattern = \"slab.*\"\nhint = \"height\"").unwrap();
+        let capitalized: Rule = toml::from_str("pattern = \"stone_h.*\"\nhint = \"Height\"").unwrap();
+        let rules = [
+            (Regex::new("^slab\\.").unwrap(), &lower),
+            (Regex::new("^stone_h\\.").unwrap(), &capitalized),
+        ];
+        let files = add_from_height(files(&["slab.ppm", "stone_h.ppm"], &rules, &options), &options);
+
+        assert_eq!(names(&files), ["slab.ppm", "slab_normal.ppm", "stone_h.ppm", "stone_h_normal.ppm"]);
+    }
+
+    /// A 4x4 8-bit height map
+    fn heights(height_at: impl Fn(u32, u32) -> u8) -> DynamicImage {
+        DynamicImage::ImageLuma8(ImageBuffer::from_fn(4, 4, |x, y| image::Luma([height_at(x, y)])))
+    }
+
+    fn settings(wrap: bool) -> NormalSettings {
+        NormalSettings { filter: NormalFilter::Sobel, strength: 1.0, wrap }
+    }
+
+    #[test]
+    fn from_height_slopes() {
+        // Rising to the right, the surface faces left
+        let normal = from_height(&heights(|x, _| x as u8 * 60), &settings(false)).to_rgb8();
+        let pixel = normal.get_pixel(1, 1);
+        assert!(pixel[0] < 128 && pixel[1] == 128, "{:?}", pixel);
+
+        // Rising downwards in the image, the surface faces up (green is Y+)
+        let normal = from_height(&heights(|_, y| y as u8 * 60), &settings(false)).to_rgb8();
+        let pixel = normal.get_pixel(1, 1);
+        assert!(pixel[1] > 128 && pixel[0] == 128, "{:?}", pixel);
+    }
+
+    #[test]
+    fn from_height_flat() {
+        let normal = from_height(&heights(|_, _| 100), &settings(true));
+        assert!(matches!(normal, DynamicImage::ImageRgb8(_)));
+        assert!(normal.to_rgb8().pixels().all(|pixel| pixel.0 == [128, 128, 255]));
+
+        // 16-bit height maps give 16-bit normal maps
+        let height = DynamicImage::ImageLuma16(ImageBuffer::from_pixel(4, 4, image::Luma([1000u16])));
+        let normal = from_height(&height, &settings(false));
+        assert!(matches!(normal, DynamicImage::ImageRgb16(_)));
+        assert!(normal.to_rgb16().pixels().all(|pixel| pixel.0 == [32768, 32768, 65535]));
+    }
+
+    #[test]
+    fn from_height_edges() {
+        let ramp = heights(|x, _| x as u8 * 60);
+        let clamped = from_height(&ramp, &settings(false)).to_rgb8();
+        let wrapped = from_height(&ramp, &settings(true)).to_rgb8();
+
+        // Wrapping around, the left edge sees the drop from the high right edge
+        assert!(clamped.get_pixel(0, 1)[0] < 128);
+        assert!(wrapped.get_pixel(0, 1)[0] > 128);
+        assert_eq!(clamped.get_pixel(1, 1), wrapped.get_pixel(1, 1));
+    }
+
+    #[test]
+    fn flip_green_keeps_the_depth() {
+        let mut normal = DynamicImage::ImageRgb8(RgbImage::from_pixel(2, 2, Rgb([10, 20, 30])));
+        flip_green(&mut normal);
+        assert_eq!(normal.as_rgb8().unwrap().get_pixel(0, 0).0, [10, 235, 30]);
+
+        let mut normal = DynamicImage::ImageRgb16(ImageBuffer::from_pixel(2, 2, Rgb([10u16, 20, 30])));
+        flip_green(&mut normal);
+        assert_eq!(normal.as_rgb16().unwrap().get_pixel(0, 0).0, [10, 65515, 30]);
+
+        let mut normal = DynamicImage::ImageLuma8(ImageBuffer::from_pixel(2, 2, image::Luma([20])));
+        flip_green(&mut normal);
+        assert_eq!(normal.as_rgba8().unwrap().get_pixel(0, 0).0, [20, 235, 20, 255]);
+    }
+}
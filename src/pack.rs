@@ -0,0 +1,216 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use clap::ValueEnum;
+use serde::Deserialize;
+use image::{DynamicImage, GrayImage, RgbImage};
+use image::imageops::{self, FilterType};
+use crate::{channel_of, decode_image, file_stem, material_key, ConversionError, Derived, MaterialKey, Options, SourceFile};
+
+/// Pack mode
+/// The ways separate maps can be packed into the channels of a single texture
+#[derive(ValueEnum, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum PackMode {
+    /// Occlusion, roughness and metallic in the red, green and blue channels
+    Orm,
+}
+
+/// Packed channels
+/// The source files which make up an ORM texture. Missing maps are filled with
+/// the value Godot uses when there's no texture.
+#[derive(Default)]
+pub struct PackedChannels {
+    pub occlusion: Option<PathBuf>,
+    pub roughness: Option<PathBuf>,
+    pub metallic: Option<PathBuf>,
+}
+
+impl PackedChannels {
+    /// Whether the channel with the given hint is one that is packed
+    fn is_packed(channel: &str) -> bool {
+        matches!(channel, "ao" | "roughness" | "metallic")
+    }
+
+    /// The slot of the channel with the given hint, if it's one that is packed
+    fn slot(&mut self, channel: &str) -> Option<&mut Option<PathBuf>> {
+        match channel {
+            "ao" => Some(&mut self.occlusion),
+            "roughness" => Some(&mut self.roughness),
+            "metallic" => Some(&mut self.metallic),
+            _ => None,
+        }
+    }
+
+    /// The source files, in channel order
+    pub fn sources(&self) -> Vec<&PathBuf> {
+        [&self.occlusion, &self.roughness, &self.metallic]
+            .into_iter()
+            .flatten()
+            .collect()
+    }
+}
+
+/// Replace the separate occlusion, roughness and metallic files with a single
+/// packed file per material
+///
+/// The maps are packed per material (see ``material_key``), e.g. "Rock_4K_AO" and "Rock_4K_Roughness".
+/// The packed file is named after the first of them, with "ORM" in place of the hint ("Rock_4K_ORM"),
+/// and takes its format, destination and rename template from it as well. It's
+/// resized according to the settings of the "orm" channel.
+/// When a material has two maps for the same channel, the extra one is converted on its own.
+pub fn group_orm(files: Vec<SourceFile>, options: &Options) -> Vec<SourceFile> {
+    let mut packs: Vec<SourceFile> = Vec::new();
+    let mut pack_index: HashMap<MaterialKey, usize> = HashMap::new();
+    let mut remaining: Vec<SourceFile> = Vec::new();
+
+    for file in files {
+        // Derived files, such as roughness converted from glossiness, only exist once converted
+        let channel = channel_of(&file, options)
+            .filter(|(channel, _)| PackedChannels::is_packed(channel) && file.derived.is_none());
+        let (Some((channel, hint_match)), Some(key)) = (channel, material_key(&file.path, options)) else {
+            remaining.push(file);
+            continue;
+        };
+
+        // Without a hint in the filename, the whole stem is replaced
+        let stem = file_stem(&file.path);
+        let (start, end) = hint_match.map_or((0, stem.len()), |hint_match| (hint_match.start, hint_match.end));
+        let packed_stem = format!("{}ORM{}", &stem[..start], &stem[end..]);
+
+        let index = *pack_index.entry(key).or_insert_with(|| {
+            let packed = Derived::Packed(PackedChannels::default());
+            packs.push(SourceFile::derived_from(&file, &packed_stem, "orm", packed, options));
+            packs.len() - 1
+        });
+
+        let Some(Derived::Packed(channels)) = packs[index].derived.as_mut() else {
+            unreachable!("Packs always have channels");
+        };
+        match channels.slot(&channel) {
+            Some(slot) if slot.is_none() => *slot = Some(file.path),
+            _ => remaining.push(file),
+        }
+    }
+
+    remaining.append(&mut packs);
+    remaining.sort_by(|a, b| a.path.cmp(&b.path));
+    remaining
+}
+
+/// Build the ORM texture from its channels
+///
+/// Missing occlusion and roughness are white (no occlusion, fully rough), and missing
+/// metallic is black (not metallic). Maps of different sizes are resized to the size
+/// of the largest one.
+pub fn pack_orm(channels: &PackedChannels) -> Result<DynamicImage, ConversionError> {
+    let load = |path: &Option<PathBuf>| -> Result<Option<GrayImage>, ConversionError> {
+        match path {
+            Some(path) => Ok(Some(decode_image(path)?.to_luma8())),
+            None => Ok(None),
+        }
+    };
+
+    let mut maps: [(Option<GrayImage>, u8); 3] = [
+        (load(&channels.occlusion)?, 255),
+        (load(&channels.roughness)?, 255),
+        (load(&channels.metallic)?, 0),
+    ];
+
+    let width = maps.iter().filter_map(|(map, _)| map.as_ref()).map(|map| map.width()).max().unwrap_or(1);
+    let height = maps.iter().filter_map(|(map, _)| map.as_ref()).map(|map| map.height()).max().unwrap_or(1);
+
+    for (map, _) in maps.iter_mut() {
+        if let Some(gray) = map.as_mut() {
+            if gray.dimensions() != (width, height) {
+                *gray = imageops::resize(gray, width, height, FilterType::Triangle);
+            }
+        }
+    }
+
+    let packed = RgbImage::from_fn(width, height, |x, y| {
+        let value = |(map, default): &(Option<GrayImage>, u8)| {
+            map.as_ref().map_or(*default, |map| map.get_pixel(x, y)[0])
+        };
+        image::Rgb([value(&maps[0]), value(&maps[1]), value(&maps[2])])
+    });
+
+    Ok(DynamicImage::ImageRgb8(packed))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+    use clap::Parser;
+    use crate::source_file;
+    use super::*;
+
+    /// The files as ``get_files`` would find them in ``/textures``
+    fn files(names: &[&str], options: &Options) -> Vec<SourceFile> {
+        names
+            .iter()
+            .map(|name| source_file(Path::new("/textures").join(name), PathBuf::from("/textures"), &[], options))
+            .collect()
+    }
+
+    /// The filenames of the files
+    fn names(files: &[SourceFile]) -> Vec<String> {
+        files.iter().map(|file| file.path.file_name().unwrap().to_string_lossy().into_owned()).collect()
+    }
+
+    #[test]
+    fn group_orm_keeps_dots_in_the_stem() {
+        let options = Options::parse_from(["gim", ".*"]);
+        let packed = group_orm(files(&["Metal.002_AO.ppm", "Metal.002_Roughness.ppm"], &options), &options);
+
+        assert_eq!(names(&packed), ["Metal.002_ORM.ppm"]);
+    }
+
+    #[test]
+    fn group_orm_packs_per_material() {
+        let options = Options::parse_from(["gim", ".*"]);
+        let names_in = ["Rock_AO.ppm", "Brick_Color.ppm", "Rock_Roughness.ppm", "Brick_AO.ppm", "Brick_Metallic.ppm"];
+        let packed = group_orm(files(&names_in, &options), &options);
+
+        assert_eq!(names(&packed), ["Brick_Color.ppm", "Brick_ORM.ppm", "Rock_ORM.ppm"]);
+
+        let Some(Derived::Packed(brick)) = &packed[1].derived else { panic!("Brick_ORM isn't packed") };
+        assert_eq!(brick.occlusion.as_deref(), Some(Path::new("/textures/Brick_AO.ppm")));
+        assert_eq!(brick.roughness, None);
+        assert_eq!(brick.metallic.as_deref(), Some(Path::new("/textures/Brick_Metallic.ppm")));
+
+        let Some(Derived::Packed(rock)) = &packed[2].derived else { panic!("Rock_ORM isn't packed") };
+        assert_eq!(rock.sources(), [Path::new("/textures/Rock_AO.ppm"), Path::new("/textures/Rock_Roughness.ppm")]);
+    }
+
+    /// Save a gray map of the given size in a directory of its own, which is unique to the test
+    fn save_map(test: &str, name: &str, size: u32, value: u8) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("gim-{}-{}", test, std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(name);
+        GrayImage::from_pixel(size, size, image::Luma([value])).save(&path).unwrap();
+        path
+    }
+
+    #[test]
+    fn pack_orm_fills_missing_channels() {
+        let roughness = save_map("pack-defaults", "rough.png", 2, 100);
+        let channels = PackedChannels { roughness: Some(roughness.clone()), ..Default::default() };
+        let packed = pack_orm(&channels).unwrap_or_else(|err| panic!("{}", err)).to_rgb8();
+        std::fs::remove_dir_all(roughness.parent().unwrap()).unwrap();
+
+        assert_eq!(packed.dimensions(), (2, 2));
+        assert!(packed.pixels().all(|pixel| pixel.0 == [255, 100, 0]));
+    }
+
+    #[test]
+    fn pack_orm_resizes_to_the_largest_map() {
+        let occlusion = save_map("pack-sizes", "ao.png", 4, 50);
+        let metallic = save_map("pack-sizes", "metal.png", 2, 200);
+        let channels = PackedChannels { occlusion: Some(occlusion.clone()), roughness: None, metallic: Some(metallic) };
+        let packed = pack_orm(&channels).unwrap_or_else(|err| panic!("{}", err)).to_rgb8();
+        std::fs::remove_dir_all(occlusion.parent().unwrap()).unwrap();
+
+        assert_eq!(packed.dimensions(), (4, 4));
+        assert!(packed.pixels().all(|pixel| pixel.0 == [50, 255, 200]));
+    }
+}
@@ -0,0 +1,237 @@
+use std::collections::HashMap;
+use clap::ValueEnum;
+use serde::Deserialize;
+use regex::Regex;
+
+/// Match mode
+/// Decides how the search (and exclude) patterns are interpreted
+#[derive(ValueEnum, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum MatchMode {
+    /// Shell-style wildcards such as ``*.tiff`` or ``rock_{albedo,normal}.tif``
+    Glob,
+    /// Regular expressions, matched against the full name
+    Regex,
+    /// The name must be exactly equal to the pattern
+    Exact,
+}
+
+/// File matcher
+/// A file is accepted when it matches at least one of the ``include`` patterns,
+/// and none of the ``exclude`` patterns
+#[derive(Default, Debug)]
+pub struct FileMatcher {
+    include: Vec<Regex>,
+    exclude: Vec<Regex>,
+}
+
+impl FileMatcher {
+    pub fn new(include: Vec<Regex>, exclude: Vec<Regex>) -> FileMatcher {
+        FileMatcher { include, exclude }
+    }
+
+    pub fn is_match(&self, subject: &str) -> bool {
+        self.include.iter().any(|regex| regex.is_match(subject))
+            && !self.exclude.iter().any(|regex| regex.is_match(subject))
+    }
+
+    /// The capture groups of the first include pattern matching ``subject``, keyed by
+    /// their number ("1", "2", ...) and, for named groups, their name
+    /// Returns ``None`` if the subject isn't accepted by the matcher.
+    pub fn captures(&self, subject: &str) -> Option<HashMap<String, String>> {
+        if !self.is_match(subject) {
+            return None;
+        }
+
+        let regex = self.include.iter().find(|regex| regex.is_match(subject))?;
+        let captures = regex.captures(subject)?;
+        let mut values: HashMap<String, String> = HashMap::new();
+
+        for (index, name) in regex.capture_names().enumerate().skip(1) {
+            let value = captures.get(index).map_or("", |m| m.as_str()).to_owned();
+            if let Some(name) = name {
+                values.insert(name.to_owned(), value.clone());
+            }
+            values.insert(index.to_string(), value);
+        }
+
+        Some(values)
+    }
+}
+
+/// Generate the ``Regex`` instance for a pattern, according to the match mode
+/// The resulting expression is always anchored, so it must match the full name.
+/// Fails with the reason if the pattern isn't valid.
+pub fn generate_regex(pattern: &str, mode: MatchMode) -> Result<Regex, String> {
+    let expression: String = match mode {
+        MatchMode::Glob => glob_to_regex(pattern)?,
+        MatchMode::Regex => pattern.to_owned(),
+        MatchMode::Exact => regex::escape(pattern),
+    };
+
+    // Set up the regular expression pattern as a string
+    let full_pattern: String = format!(r"^(?:{})$", expression);
+
+    // Build the Regex instance based on the full_pattern string
+    Regex::new(&full_pattern).map_err(|err| format!("\"{}\": {}", pattern, err))
+}
+
+/// Translate a glob pattern into a regular expression
+/// Every wildcard becomes a capture group, so it can be used in rename templates.
+///
+/// Supported syntax:
+/// * ``*`` matches anything except ``/``
+/// * ``**`` matches anything, including ``/`` (and ``**/`` also matches no directory at all)
+/// * ``?`` matches a single character except ``/``
+/// * ``[abc]``, ``[a-z]`` and ``[!abc]`` match character classes
+/// * ``{a,b}`` matches either of the alternatives
+///
+/// Fails if a ``[`` or ``{`` isn't closed.
+fn glob_to_regex(pattern: &str) -> Result<String, String> {
+    let mut expression = String::new();
+    let mut chars = pattern.chars().peekable();
+    let mut in_braces: usize = 0;
+
+    while let Some(c) = chars.next() {
+        match c {
+            '*' => {
+                if chars.peek() == Some(&'*') {
+                    chars.next();
+
+                    // "**/" may also match zero directories
+                    if chars.peek() == Some(&'/') {
+                        chars.next();
+                        expression.push_str("(?:(.*)/)?");
+                    } else {
+                        expression.push_str("(.*)");
+                    }
+                } else {
+                    expression.push_str("([^/]*)");
+                }
+            },
+            '?' => expression.push_str("([^/])"),
+            '[' => {
+                expression.push('[');
+                if chars.peek() == Some(&'!') {
+                    chars.next();
+                    expression.push('^');
+                }
+
+                // A "]" right at the start is part of the class, as in "[]abc]"
+                let mut is_first = true;
+                let mut is_closed = false;
+                for class_char in chars.by_ref() {
+                    if class_char == ']' && !is_first {
+                        is_closed = true;
+                        break;
+                    }
+                    if matches!(class_char, '\\' | '[' | ']' | '&' | '~') {
+                        expression.push('\\');
+                    }
+                    expression.push(class_char);
+                    is_first = false;
+                }
+
+                if !is_closed {
+                    return Err(format!("\"{}\": unclosed [", pattern));
+                }
+                expression.push(']');
+            },
+            '{' => {
+                in_braces += 1;
+                expression.push_str("(?:");
+            },
+            ',' if in_braces > 0 => expression.push('|'),
+            '}' if in_braces > 0 => {
+                in_braces -= 1;
+                expression.push(')');
+            },
+            _ => expression.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+
+    if in_braces > 0 {
+        return Err(format!("\"{}\": unclosed {{", pattern));
+    }
+
+    Ok(expression)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Whether the glob pattern matches the subject, as the file matcher would apply it
+    fn glob_matches(pattern: &str, subject: &str) -> bool {
+        generate_regex(pattern, MatchMode::Glob).unwrap().is_match(subject)
+    }
+
+    #[test]
+    fn glob_star_stays_in_directory() {
+        assert!(glob_matches("*.tiff", "rock.tiff"));
+        assert!(!glob_matches("*.tiff", "rock/albedo.tiff"));
+        assert!(!glob_matches("*.tiff", "rock.tiff.bak"));
+    }
+
+    #[test]
+    fn glob_double_star_slash_matches_any_depth() {
+        assert!(glob_matches("**/*.png", "albedo.png"));
+        assert!(glob_matches("**/*.png", "rock/albedo.png"));
+        assert!(glob_matches("**/*.png", "lib/rock/albedo.png"));
+        assert!(glob_matches("lib/**", "lib/rock/albedo.png"));
+        assert!(!glob_matches("lib/**/*.png", "rock/albedo.png"));
+    }
+
+    #[test]
+    fn glob_question_mark_matches_one_character() {
+        assert!(glob_matches("rock_?k.png", "rock_4k.png"));
+        assert!(!glob_matches("rock_?k.png", "rock_16k.png"));
+        assert!(!glob_matches("a?b", "a/b"));
+    }
+
+    #[test]
+    fn glob_braces_match_alternatives() {
+        let pattern = "rock_{albedo,normal}.tif";
+        assert!(glob_matches(pattern, "rock_albedo.tif"));
+        assert!(glob_matches(pattern, "rock_normal.tif"));
+        assert!(!glob_matches(pattern, "rock_height.tif"));
+        assert!(glob_matches("{a,b{c,d}}.png", "bd.png"));
+    }
+
+    #[test]
+    fn glob_character_classes() {
+        assert!(glob_matches("rock_[0-9].png", "rock_4.png"));
+        assert!(!glob_matches("rock_[0-9].png", "rock_x.png"));
+        assert!(glob_matches("rock_[!x].png", "rock_a.png"));
+        assert!(!glob_matches("rock_[!x].png", "rock_x.png"));
+        assert!(glob_matches("[]a].png", "].png"));
+        assert!(glob_matches("[[].png", "[.png"));
+    }
+
+    #[test]
+    fn glob_escapes_regex_syntax() {
+        assert!(glob_matches("rock (1).png", "rock (1).png"));
+        assert!(!glob_matches("rock.png", "rockxpng"));
+        assert!(glob_matches("a}b,c.png", "a}b,c.png"));
+    }
+
+    #[test]
+    fn glob_wildcards_are_captured() {
+        let captures = FileMatcher::new(vec![generate_regex("*_*.png", MatchMode::Glob).unwrap()], Vec::new())
+            .captures("rock_albedo.png")
+            .unwrap();
+
+        assert_eq!(captures["1"], "rock");
+        assert_eq!(captures["2"], "albedo");
+    }
+
+    #[test]
+    fn unbalanced_patterns_are_rejected() {
+        assert!(generate_regex("metal_{plate", MatchMode::Glob).is_err());
+        assert!(generate_regex("metal_[abc", MatchMode::Glob).is_err());
+        assert!(generate_regex("[!", MatchMode::Glob).is_err());
+        assert!(generate_regex("[", MatchMode::Regex).is_err());
+        assert!(generate_regex("(", MatchMode::Regex).is_err());
+        assert!(generate_regex("(", MatchMode::Exact).is_ok());
+    }
+}
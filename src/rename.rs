@@ -0,0 +1,212 @@
+use std::collections::HashMap;
+use regex::Regex;
+use material::{HintDictionary, HintMatch};
+
+/// Rename context
+/// The values available to the placeholders of a rename template
+pub struct RenameContext<'a> {
+    /// The filename of the source, without extension
+    pub stem: &'a str,
+    /// The extension of the output format
+    pub ext: &'a str,
+    /// The material channel, such as "albedo", if known
+    pub channel: Option<&'a str>,
+    /// Where the channel hint was found in the stem, if it was detected from it
+    pub hint_match: Option<&'a HintMatch>,
+    /// Recognizes the resolution and vendor tags left out of ``{material}``
+    pub dictionary: &'a HintDictionary,
+    /// Capture groups from the search pattern, by number ("1") or name
+    pub captures: &'a HashMap<String, String>,
+}
+
+/// Render a rename template such as ``{material}_{channel}.{ext}``
+///
+/// Supported placeholders:
+/// * ``{stem}``: the source filename without extension
+/// * ``{stem_lower}``: same as above, in lowercase
+/// * ``{ext}``: the extension of the output format
+/// * ``{channel}``: the material channel, such as "albedo" or "normal"
+/// * ``{material}``: the source filename, without channel, resolution and vendor tags, in lowercase
+/// * ``{1}``, ``{2}``, ``{name}``: capture groups from the search pattern
+///
+/// If the template doesn't contain ``{ext}``, the extension is appended.
+pub fn render(template: &str, context: &RenameContext) -> Result<String, String> {
+    let mut name = substitute(template, |key| resolve(key, context))?;
+
+    if !template.contains("{ext}") {
+        name.push('.');
+        name.push_str(context.ext);
+    }
+
+    validate_filename(name)
+}
+
+/// Render a material name template such as ``{dir}_{group}``
+///
+/// Supported placeholders:
+/// * ``{group}``: the texture set, such as "brick"
+/// * ``{dir}``: the name of the input directory the textures were found in
+///
+/// The ".tres" extension is appended, unless the template ends with it.
+pub fn render_material_name(template: &str, group: &str, dir: &str) -> Result<String, String> {
+    let mut name = substitute(template, |key| match key {
+        "group" => Ok(group.to_owned()),
+        "dir" => Ok(dir.to_owned()),
+        _ => Err(format!("unknown placeholder {{{}}}", key)),
+    })?;
+
+    if !name.ends_with(".tres") {
+        name.push_str(".tres");
+    }
+
+    validate_filename(name)
+}
+
+/// Replace the placeholders in ``template`` with the values ``resolve`` finds for them
+fn substitute<F>(template: &str, resolve: F) -> Result<String, String>
+where
+    F: Fn(&str) -> Result<String, String>,
+{
+    let placeholder = Regex::new(r"\{([A-Za-z0-9_]+)\}").unwrap();
+    let mut name = String::new();
+    let mut last_end: usize = 0;
+
+    for captures in placeholder.captures_iter(template) {
+        let whole = captures.get(0).unwrap();
+        name.push_str(&template[last_end..whole.start()]);
+        name.push_str(&resolve(&captures[1])?);
+        last_end = whole.end();
+    }
+
+    name.push_str(&template[last_end..]);
+    Ok(name)
+}
+
+/// Placeholders could otherwise be used to escape the destination directory
+fn validate_filename(name: String) -> Result<String, String> {
+    match name.contains('/') || name.contains('\\') {
+        true => Err(format!("\"{}\" is not a valid filename", name)),
+        false => Ok(name),
+    }
+}
+
+/// Find the value of a single placeholder
+fn resolve(key: &str, context: &RenameContext) -> Result<String, String> {
+    match key {
+        "stem" => Ok(context.stem.to_owned()),
+        "stem_lower" => Ok(context.stem.to_lowercase()),
+        "ext" => Ok(context.ext.to_owned()),
+        "channel" => context.channel
+            .map(|channel| channel.to_owned())
+            .ok_or_else(|| String::from("no material channel detected for {channel}")),
+        "material" => Ok(material_name(context.stem, context.hint_match, context.dictionary)),
+        _ => context.captures
+            .get(key)
+            .cloned()
+            .ok_or_else(|| format!("unknown placeholder {{{}}}", key)),
+    }
+}
+
+/// The name of the material a texture belongs to, derived from the filename
+/// by leaving out the channel (e.g. "albedo") and other tags, such as resolution (e.g. "4K")
+///
+/// For instance "Rock042_4K_Color" becomes "rock042".
+pub fn material_name(stem: &str, hint_match: Option<&HintMatch>, dictionary: &HintDictionary) -> String {
+    // Blank out the channel hint, so it falls away with the separators
+    let mut stem: String = stem.to_owned();
+    if let Some(hint_match) = hint_match {
+        stem.replace_range(hint_match.start..hint_match.end, "_");
+    }
+
+    stem.split(['_', '-', ' ', '.'])
+        .filter(|token| !token.is_empty())
+        .filter(|token| !dictionary.is_tag(token))
+        .map(|token| token.to_lowercase())
+        .collect::<Vec<String>>()
+        .join("_")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pattern::{generate_regex, FileMatcher, MatchMode};
+
+    /// Render ``template`` for the source ``stem``, with the channel detected from it
+    fn render_stem(template: &str, stem: &str, captures: &HashMap<String, String>) -> Result<String, String> {
+        let dictionary = HintDictionary::default();
+        let hint_match = dictionary.detect(stem);
+        let context = RenameContext {
+            stem,
+            ext: "png",
+            channel: hint_match.as_ref().map(|hint_match| hint_match.channel),
+            hint_match: hint_match.as_ref(),
+            dictionary: &dictionary,
+            captures,
+        };
+
+        render(template, &context)
+    }
+
+    #[test]
+    fn render_material_and_channel() {
+        // The stem of "Rock042_4K_Color.tif", converted to PNG
+        assert_eq!(render_stem("{material}_{channel}", "Rock042_4K_Color", &HashMap::new()).unwrap(), "rock042_albedo.png");
+        assert_eq!(render_stem("{stem_lower}", "Rock042_4K_Color", &HashMap::new()).unwrap(), "rock042_4k_color.png");
+        assert!(render_stem("{channel}", "Rock042", &HashMap::new()).is_err());
+        assert!(render_stem("{unknown}", "Rock042_4K_Color", &HashMap::new()).is_err());
+    }
+
+    #[test]
+    fn render_capture_groups() {
+        let regex = generate_regex(r"(\w)_(?P<name>[A-Za-z]+)_\w+\.tif", MatchMode::Regex).unwrap();
+        let captures = FileMatcher::new(vec![regex], Vec::new()).captures("T_Brick_Color.tif").unwrap();
+
+        assert_eq!(render_stem("{2}_{1}", "T_Brick_Color", &captures).unwrap(), "Brick_T.png");
+        assert_eq!(render_stem("{name}_{channel}", "T_Brick_Color", &captures).unwrap(), "Brick_albedo.png");
+        assert!(render_stem("{3}", "T_Brick_Color", &captures).is_err());
+    }
+
+    #[test]
+    fn render_appends_the_extension() {
+        assert_eq!(render_stem("{stem}", "Rock", &HashMap::new()).unwrap(), "Rock.png");
+        assert_eq!(render_stem("{stem}.{ext}", "Rock", &HashMap::new()).unwrap(), "Rock.png");
+        assert_eq!(render_stem("{stem}-{ext}", "Rock", &HashMap::new()).unwrap(), "Rock-png");
+    }
+
+    #[test]
+    fn render_rejects_path_separators() {
+        let captures: HashMap<String, String> = HashMap::from([(String::from("1"), String::from("../escape"))]);
+
+        assert!(render_stem("{1}", "Rock", &captures).is_err());
+        assert!(render_stem("sub/{stem}", "Rock", &HashMap::new()).is_err());
+        assert!(render_stem("sub\\{stem}", "Rock", &HashMap::new()).is_err());
+    }
+
+    #[test]
+    fn render_material_names() {
+        assert_eq!(render_material_name("{group}", "brick", "walls").unwrap(), "brick.tres");
+        assert_eq!(render_material_name("{dir}_{group}.tres", "brick", "walls").unwrap(), "walls_brick.tres");
+        assert!(render_material_name("{stem}", "brick", "walls").is_err());
+        assert!(render_material_name("{dir}/{group}", "brick", "walls").is_err());
+    }
+
+    #[test]
+    fn material_name_leaves_out_hint_and_tags() {
+        let dictionary = HintDictionary::default();
+        let name = |stem: &str| material_name(stem, dictionary.detect(stem).as_ref(), &dictionary);
+
+        assert_eq!(name("Rock042_4K_Color"), "rock042");
+        assert_eq!(name("rock_wall_nor_gl_4k"), "rock_wall");
+        assert_eq!(name("Metal_Plate_BaseColor_2048px"), "metal_plate");
+        assert_eq!(name("Rock"), "rock");
+        assert_eq!(material_name("Rock_Color", None, &dictionary), "rock_color");
+    }
+
+    #[test]
+    fn validate_filenames() {
+        assert_eq!(validate_filename(String::from("rock.png")).unwrap(), "rock.png");
+        assert_eq!(validate_filename(String::from("..rock.png")).unwrap(), "..rock.png");
+        assert!(validate_filename(String::from("a/b.png")).is_err());
+        assert!(validate_filename(String::from("a\\b.png")).is_err());
+    }
+}
@@ -0,0 +1,214 @@
+use clap::ValueEnum;
+use serde::Deserialize;
+use image::DynamicImage;
+use image::imageops::FilterType;
+
+/// Power-of-two mode
+/// How dimensions which aren't a power of two are rounded to one
+#[derive(ValueEnum, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum PotMode {
+    /// The closest power of two, e.g. 1000 becomes 1024, and 1400 becomes 1024
+    Nearest,
+    /// The next power of two, e.g. 1400 becomes 2048
+    Up,
+    /// The previous power of two, e.g. 1400 becomes 1024
+    Down,
+}
+
+/// Resize filter
+/// The resampling filters offered by the image library, from fastest to sharpest
+#[derive(ValueEnum, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ResizeFilter {
+    Nearest,
+    Triangle,
+    Catmullrom,
+    Gaussian,
+    Lanczos3,
+}
+
+impl From<ResizeFilter> for FilterType {
+    fn from(value: ResizeFilter) -> Self {
+        match value {
+            ResizeFilter::Nearest => FilterType::Nearest,
+            ResizeFilter::Triangle => FilterType::Triangle,
+            ResizeFilter::Catmullrom => FilterType::CatmullRom,
+            ResizeFilter::Gaussian => FilterType::Gaussian,
+            ResizeFilter::Lanczos3 => FilterType::Lanczos3,
+        }
+    }
+}
+
+/// Resize settings
+/// How a file is resized before it's saved. Settings which aren't given leave
+/// the size alone, and the filter defaults to Lanczos.
+#[derive(Default, Clone, Copy, Debug)]
+pub struct ResizeSettings {
+    /// The longest side is scaled down to this size, keeping the aspect ratio
+    pub max_size: Option<u32>,
+    /// Scale factor, e.g. 0.5 for half the size
+    pub scale: Option<f32>,
+    pub pot: Option<PotMode>,
+    pub filter: Option<ResizeFilter>,
+}
+
+impl ResizeSettings {
+    /// Override these settings with those given in ``other``
+    pub fn merge(self, other: ResizeSettings) -> ResizeSettings {
+        ResizeSettings {
+            max_size: other.max_size.or(self.max_size),
+            scale: other.scale.or(self.scale),
+            pot: other.pot.or(self.pot),
+            filter: other.filter.or(self.filter),
+        }
+    }
+
+    /// Check the scale and the maximum size, which would otherwise shrink images to a single pixel
+    pub fn validate(&self) -> Result<(), String> {
+        if let Some(scale) = self.scale {
+            check_scale(scale).map_err(|reason| format!("scale: {}", reason))?;
+        }
+
+        if let Some(max_size) = self.max_size {
+            check_max_size(max_size).map_err(|reason| format!("max_size: {}", reason))?;
+        }
+
+        Ok(())
+    }
+
+    /// The size an image of ``width`` by ``height`` ends up with
+    ///
+    /// The scale is applied first, then the maximum size, and lastly the dimensions
+    /// are rounded to a power of two, without exceeding the maximum size.
+    pub fn target_size(&self, width: u32, height: u32) -> (u32, u32) {
+        let (mut w, mut h) = (width as f64, height as f64);
+
+        if let Some(scale) = self.scale {
+            w *= f64::from(scale);
+            h *= f64::from(scale);
+        }
+
+        if let Some(max_size) = self.max_size {
+            let ratio = f64::from(max_size) / w.max(h);
+            if ratio < 1.0 {
+                w *= ratio;
+                h *= ratio;
+            }
+        }
+
+        let (mut w, mut h) = ((w.round() as u32).max(1), (h.round() as u32).max(1));
+
+        if let Some(pot) = self.pot {
+            w = power_of_two(w, pot, self.max_size);
+            h = power_of_two(h, pot, self.max_size);
+        }
+
+        (w, h)
+    }
+}
+
+/// Check a scale factor, which has to be a positive number
+pub fn check_scale(scale: f32) -> Result<(), String> {
+    match scale > 0.0 && scale.is_finite() {
+        true => Ok(()),
+        false => Err(format!("expected a positive number, not {}", scale)),
+    }
+}
+
+/// Check a maximum size, which has to be at least one pixel
+pub fn check_max_size(max_size: u32) -> Result<(), String> {
+    match max_size {
+        0 => Err(String::from("expected at least 1")),
+        _ => Ok(()),
+    }
+}
+
+/// Round ``size`` to a power of two, staying within ``max_size``
+fn power_of_two(size: u32, mode: PotMode, max_size: Option<u32>) -> u32 {
+    let up = size.next_power_of_two();
+    let down = if up == size { size } else { up / 2 };
+
+    let rounded = match mode {
+        PotMode::Up => up,
+        PotMode::Down => down,
+        PotMode::Nearest if up - size <= size - down => up,
+        PotMode::Nearest => down,
+    };
+
+    match max_size {
+        Some(max_size) if rounded > max_size => down.max(1),
+        _ => rounded,
+    }
+}
+
+/// Resize the image according to the settings, if its size changes
+pub fn resize(img: DynamicImage, settings: &ResizeSettings) -> DynamicImage {
+    let (width, height) = settings.target_size(img.width(), img.height());
+
+    if (width, height) == (img.width(), img.height()) {
+        return img;
+    }
+
+    let filter = settings.filter.unwrap_or(ResizeFilter::Lanczos3);
+    img.resize_exact(width, height, filter.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Resize settings with the given scale and maximum size
+    fn settings(scale: Option<f32>, max_size: Option<u32>, pot: Option<PotMode>) -> ResizeSettings {
+        ResizeSettings { max_size, scale, pot, filter: None }
+    }
+
+    #[test]
+    fn power_of_two_rounds_as_documented() {
+        assert_eq!(power_of_two(1000, PotMode::Nearest, None), 1024);
+        assert_eq!(power_of_two(1400, PotMode::Nearest, None), 1024);
+        assert_eq!(power_of_two(1400, PotMode::Up, None), 2048);
+        assert_eq!(power_of_two(1400, PotMode::Down, None), 1024);
+        assert_eq!(power_of_two(1024, PotMode::Up, None), 1024);
+        assert_eq!(power_of_two(1024, PotMode::Down, None), 1024);
+        assert_eq!(power_of_two(1, PotMode::Down, None), 1);
+    }
+
+    #[test]
+    fn power_of_two_stays_within_the_maximum_size() {
+        assert_eq!(power_of_two(1400, PotMode::Up, Some(1500)), 1024);
+        assert_eq!(power_of_two(1000, PotMode::Nearest, Some(1000)), 512);
+        assert_eq!(power_of_two(1000, PotMode::Up, Some(1024)), 1024);
+    }
+
+    #[test]
+    fn target_size_keeps_the_size_without_settings() {
+        assert_eq!(ResizeSettings::default().target_size(1000, 600), (1000, 600));
+    }
+
+    #[test]
+    fn target_size_scales_then_limits() {
+        assert_eq!(settings(Some(0.5), None, None).target_size(4096, 2048), (2048, 1024));
+        assert_eq!(settings(None, Some(2048), None).target_size(4096, 1024), (2048, 512));
+        assert_eq!(settings(None, Some(2048), None).target_size(1000, 600), (1000, 600));
+        assert_eq!(settings(Some(2.0), Some(1500), None).target_size(1000, 500), (1500, 750));
+        assert_eq!(settings(Some(0.001), None, None).target_size(100, 100), (1, 1));
+    }
+
+    #[test]
+    fn target_size_rounds_to_a_power_of_two() {
+        assert_eq!(settings(None, None, Some(PotMode::Nearest)).target_size(1000, 1400), (1024, 1024));
+        assert_eq!(settings(None, None, Some(PotMode::Up)).target_size(1000, 1400), (1024, 2048));
+        assert_eq!(settings(None, Some(1500), Some(PotMode::Up)).target_size(1500, 700), (1024, 1024));
+    }
+
+    #[test]
+    fn validate_rejects_sizes_which_collapse_the_image() {
+        assert!(settings(Some(0.5), Some(2048), None).validate().is_ok());
+        assert!(settings(Some(0.0), None, None).validate().is_err());
+        assert!(settings(Some(-2.0), None, None).validate().is_err());
+        assert!(settings(Some(f32::NAN), None, None).validate().is_err());
+        assert!(settings(Some(f32::INFINITY), None, None).validate().is_err());
+        assert!(settings(None, Some(0), None).validate().is_err());
+    }
+}
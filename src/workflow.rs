@@ -0,0 +1,276 @@
+use std::collections::HashMap;
+use image::{DynamicImage, GrayImage, ImageBuffer, Luma, Rgb, RgbImage, RgbaImage};
+use image::imageops::{self, FilterType};
+use material::HintMatch;
+use crate::{channel_of, file_stem, material_key, replace_hint, with_stem, Derived, MaterialKey, Options, SourceFile};
+
+/// The reflectance of non-metals, which the specular workflow leaves implicit
+const DIELECTRIC_SPECULAR: f32 = 0.04;
+
+/// Convert the maps of the specular/glossiness workflow into those of the
+/// metallic/roughness workflow, which is the one Godot uses
+///
+/// * Glossiness maps are inverted into roughness maps ("Rock_Gloss" becomes "Rock_Roughness")
+/// * Specular maps are turned into metallic maps ("Rock_Specular" becomes "Rock_Metallic")
+/// * Diffuse maps of materials with a specular map are corrected into albedo maps,
+///   since metals get their color from the specular map
+///
+/// The maps are looked up per material, see ``material_key``. Materials which already have a
+/// roughness (or metallic) map keep it, and their glossiness (or specular) map is left as is.
+pub fn convert_spec_gloss(mut files: Vec<SourceFile>, options: &Options) -> Vec<SourceFile> {
+    let mut channels: HashMap<MaterialKey, HashMap<String, usize>> = HashMap::new();
+    let mut hint_matches: Vec<Option<HintMatch>> = Vec::with_capacity(files.len());
+
+    for (index, file) in files.iter().enumerate() {
+        let (channel, hint_match) = match channel_of(file, options) {
+            Some((channel, hint_match)) => (Some(channel), hint_match),
+            None => (None, None),
+        };
+
+        if let (Some(channel), Some(key)) = (channel, material_key(&file.path, options)) {
+            channels.entry(key).or_default().entry(channel).or_insert(index);
+        }
+
+        hint_matches.push(hint_match);
+    }
+
+    for material in channels.values() {
+        let path_of = |channel: &str| material.get(channel).map(|&index| files[index].path.clone());
+        let (gloss, specular, diffuse) = (path_of("gloss"), path_of("specular"), path_of("albedo"));
+
+        if let (Some(gloss), false) = (gloss, material.contains_key("roughness")) {
+            let index = material["gloss"];
+            derive(&mut files[index], hint_matches[index].as_ref(), options, "roughness", Derived::RoughnessFromGloss(gloss));
+        }
+
+        if let (Some(specular), false) = (specular, material.contains_key("metallic")) {
+            let index = material["specular"];
+            let metallic = Derived::MetallicFromSpecular { specular: specular.clone(), diffuse: diffuse.clone() };
+            derive(&mut files[index], hint_matches[index].as_ref(), options, "metallic", metallic);
+
+            if let Some(diffuse) = diffuse {
+                let index = material["albedo"];
+                let albedo = Derived::AlbedoFromSpecular { diffuse, specular };
+                derive(&mut files[index], hint_matches[index].as_ref(), options, "albedo", albedo);
+            }
+        }
+    }
+
+    files.sort_by(|a, b| a.path.cmp(&b.path));
+    files
+}
+
+/// Turn the file into one derived from its sources, and rename it after the new channel
+/// The settings of the new channel apply to it, e.g. those of ``[channels.roughness]``.
+/// Files without a hint in their name (whose channel comes from a rule) keep their name.
+fn derive(file: &mut SourceFile, hint_match: Option<&HintMatch>, options: &Options, channel: &str, derived: Derived) {
+    if let Some(hint_match) = hint_match {
+        file.path = with_stem(&file.path, &replace_hint(&file_stem(&file.path), hint_match, channel));
+    }
+
+    file.hint = Some(channel.to_owned());
+    file.resize = options.resize_settings(Some(channel), file.rule.as_ref());
+    file.depth = options.depth_settings(Some(channel), file.rule.as_ref());
+    file.derived = Some(derived);
+}
+
+/// Invert a glossiness map into a roughness map, keeping 16-bit precision if there is any
+pub fn roughness_from_gloss(gloss: &DynamicImage) -> DynamicImage {
+    let mut roughness = match gloss.color().bytes_per_pixel() / gloss.color().channel_count() {
+        1 => DynamicImage::ImageLuma8(gloss.to_luma8()),
+        _ => DynamicImage::ImageLuma16(gloss.to_luma16()),
+    };
+
+    roughness.invert();
+    roughness
+}
+
+/// Approximate the metallic map from a specular map and, if there is one, the diffuse map
+///
+/// Follows the conversion of the glTF specular/glossiness extension: where the specular
+/// reflectance rises above that of non-metals, and the diffuse color fades, the surface is metal.
+pub fn metallic_from_specular(specular: &DynamicImage, diffuse: Option<&DynamicImage>) -> DynamicImage {
+    let specular = specular.to_rgb8();
+    let (width, height) = specular.dimensions();
+    let diffuse = diffuse.map(|diffuse| resize_to(diffuse.to_rgb8(), width, height));
+
+    let metallic: GrayImage = ImageBuffer::from_fn(width, height, |x, y| {
+        let specular = linear(specular.get_pixel(x, y));
+        let diffuse = diffuse.as_ref().map_or([0.0; 3], |diffuse| linear(diffuse.get_pixel(x, y)));
+
+        Luma([(solve_metallic(diffuse, specular) * 255.0).round() as u8])
+    });
+
+    DynamicImage::ImageLuma8(metallic)
+}
+
+/// Approximate the albedo map from a diffuse and specular map
+///
+/// Non-metals keep their diffuse color, while metals take the color of their specular reflection.
+/// The alpha channel of the diffuse map is preserved.
+pub fn albedo_from_specular(diffuse: &DynamicImage, specular: &DynamicImage) -> DynamicImage {
+    let has_alpha = diffuse.color().has_alpha();
+    let diffuse = diffuse.to_rgba8();
+    let (width, height) = diffuse.dimensions();
+    let specular = resize_to(specular.to_rgb8(), width, height);
+
+    let albedo: RgbaImage = ImageBuffer::from_fn(width, height, |x, y| {
+        let pixel = diffuse.get_pixel(x, y);
+        let diffuse = linear(&Rgb([pixel[0], pixel[1], pixel[2]]));
+        let specular = linear(specular.get_pixel(x, y));
+
+        let metallic = solve_metallic(diffuse, specular);
+        let one_minus_specular_strength = 1.0 - specular.iter().cloned().fold(0.0, f32::max);
+
+        let albedo: [u8; 3] = [0, 1, 2].map(|c| {
+            let from_diffuse = diffuse[c] * one_minus_specular_strength
+                / (1.0 - DIELECTRIC_SPECULAR)
+                / (1.0 - metallic).max(f32::EPSILON);
+            let from_specular = (specular[c] - DIELECTRIC_SPECULAR * (1.0 - metallic)) / metallic.max(f32::EPSILON);
+            let weight = metallic * metallic;
+
+            to_srgb(from_diffuse + (from_specular - from_diffuse) * weight)
+        });
+
+        image::Rgba([albedo[0], albedo[1], albedo[2], pixel[3]])
+    });
+
+    match has_alpha {
+        true => DynamicImage::ImageRgba8(albedo),
+        false => DynamicImage::ImageRgb8(DynamicImage::ImageRgba8(albedo).to_rgb8()),
+    }
+}
+
+/// Solve how metallic a surface is, given its linear diffuse and specular colors
+fn solve_metallic(diffuse: [f32; 3], specular: [f32; 3]) -> f32 {
+    let specular_brightness = brightness(specular);
+
+    if specular_brightness < DIELECTRIC_SPECULAR {
+        return 0.0;
+    }
+
+    let one_minus_specular_strength = 1.0 - specular.iter().cloned().fold(0.0, f32::max);
+    let a = DIELECTRIC_SPECULAR;
+    let b = brightness(diffuse) * one_minus_specular_strength / (1.0 - a) + specular_brightness - 2.0 * a;
+    let c = a - specular_brightness;
+    let discriminant = (b * b - 4.0 * a * c).max(0.0);
+
+    ((-b + discriminant.sqrt()) / (2.0 * a)).clamp(0.0, 1.0)
+}
+
+/// The perceived brightness of a linear color
+fn brightness(color: [f32; 3]) -> f32 {
+    (0.299 * color[0] * color[0] + 0.587 * color[1] * color[1] + 0.114 * color[2] * color[2]).sqrt()
+}
+
+/// Decode an sRGB pixel into linear values
+fn linear(pixel: &Rgb<u8>) -> [f32; 3] {
+    pixel.0.map(|value| {
+        let value = f32::from(value) / 255.0;
+        match value <= 0.04045 {
+            true => value / 12.92,
+            false => ((value + 0.055) / 1.055).powf(2.4),
+        }
+    })
+}
+
+/// Encode a linear value as sRGB
+fn to_srgb(value: f32) -> u8 {
+    let value = value.clamp(0.0, 1.0);
+    let encoded = match value <= 0.0031308 {
+        true => value * 12.92,
+        false => 1.055 * value.powf(1.0 / 2.4) - 0.055,
+    };
+
+    (encoded * 255.0).round() as u8
+}
+
+/// Resize the map if it doesn't have the given size
+fn resize_to(image: RgbImage, width: u32, height: u32) -> RgbImage {
+    match image.dimensions() == (width, height) {
+        true => image,
+        false => imageops::resize(&image, width, height, FilterType::Triangle),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+    use clap::Parser;
+    use regex::Regex;
+    use crate::config::Rule;
+    use crate::source_file;
+    use super::*;
+
+    /// The files as ``get_files`` would find them in ``/textures``, with the rules applied
+    fn files(names: &[&str], rules: &[(Regex, &Rule)], options: &Options) -> Vec<SourceFile> {
+        names
+            .iter()
+            .map(|name| source_file(Path::new("/textures").join(name), PathBuf::from("/textures"), rules, options))
+            .collect()
+    }
+
+    /// The filenames of the files
+    fn names(files: &[SourceFile]) -> Vec<String> {
+        files.iter().map(|file| file.path.file_name().unwrap().to_string_lossy().into_owned()).collect()
+    }
+
+    #[test]
+    fn convert_spec_gloss_keeps_dots_in_the_stem() {
+        let options = Options::parse_from(["gim", ".*"]);
+        let files = convert_spec_gloss(files(&["Wood.003_Gloss.ppm"], &[], &options), &options);
+
+        assert_eq!(names(&files), ["Wood.003_Roughness.ppm"]);
+        assert!(matches!(files[0].derived, Some(Derived::RoughnessFromGloss(_))));
+    }
+
+    #[test]
+    fn convert_spec_gloss_uses_the_hint_of_a_rule() {
+        let options = Options::parse_from(["gim", ".*"]);
+        let rule: Rule = toml::from_str("pattern = \"slab.*\"\nhint = \"gloss\"").unwrap();
+        let rules = [(Regex::new("^slab\\.").unwrap(), &rule)];
+        let files = convert_spec_gloss(files(&["slab.ppm"], &rules, &options), &options);
+
+        assert_eq!(names(&files), ["slab.ppm"]);
+        assert!(matches!(files[0].derived, Some(Derived::RoughnessFromGloss(_))));
+    }
+
+    /// A 2x2 map of a single color
+    fn solid(color: [u8; 3]) -> DynamicImage {
+        DynamicImage::ImageRgb8(RgbImage::from_pixel(2, 2, Rgb(color)))
+    }
+
+    #[test]
+    fn dielectric_specular_is_not_metallic() {
+        // A linear reflectance of 0.04 is 56 in sRGB
+        let metallic = metallic_from_specular(&solid([56, 56, 56]), Some(&solid([128, 90, 60]))).to_luma8();
+        assert!(metallic.pixels().all(|pixel| pixel[0] == 0), "{:?}", metallic.get_pixel(0, 0));
+
+        let metallic = metallic_from_specular(&solid([30, 30, 30]), None).to_luma8();
+        assert!(metallic.pixels().all(|pixel| pixel[0] == 0));
+    }
+
+    #[test]
+    fn bright_specular_over_dark_diffuse_is_metallic() {
+        let metallic = metallic_from_specular(&solid([230, 220, 200]), Some(&solid([10, 10, 10]))).to_luma8();
+        assert!(metallic.pixels().all(|pixel| pixel[0] >= 250), "{:?}", metallic.get_pixel(0, 0));
+
+        // The diffuse map is resized to the specular map
+        let diffuse = DynamicImage::ImageRgb8(RgbImage::from_pixel(4, 4, Rgb([10, 10, 10])));
+        let metallic = metallic_from_specular(&solid([230, 220, 200]), Some(&diffuse));
+        assert_eq!((metallic.width(), metallic.height()), (2, 2));
+    }
+
+    #[test]
+    fn gloss_inverts_to_roughness() {
+        let gloss = DynamicImage::ImageLuma8(GrayImage::from_pixel(2, 2, Luma([200])));
+        let roughness = roughness_from_gloss(&gloss);
+        assert!(matches!(roughness, DynamicImage::ImageLuma8(_)));
+        assert_eq!(roughness.to_luma8().get_pixel(0, 0)[0], 55);
+
+        let gloss = DynamicImage::ImageLuma16(ImageBuffer::from_pixel(2, 2, Luma([0x1234u16])));
+        let roughness = roughness_from_gloss(&gloss);
+        assert!(matches!(roughness, DynamicImage::ImageLuma16(_)));
+        assert_eq!(roughness.to_luma16().get_pixel(0, 0)[0], 0xffff - 0x1234);
+    }
+}